@@ -0,0 +1,27 @@
+//! Print a saved game as pretty JSON, whatever format it was saved in. The
+//! output is itself a valid save, so it can be edited and loaded again.
+
+extern crate roguelike;
+
+use std::env;
+use std::path::Path;
+use std::process;
+
+const USAGE: &'static str = "Usage: savedump SAVE
+
+Print the saved game SAVE as pretty JSON, as it is stored.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.len() != 1 || args[0] == "--help" || args[0] == "-h" {
+        eprintln!("{}", USAGE);
+        process::exit(2);
+    }
+    match roguelike::dump_save(Path::new(&args[0])) {
+        Ok(save) => println!("{}", save.pretty()),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,475 @@
+//! A compact binary encoding of saves. It holds exactly what the JSON
+//! encoding would, so a binary save decodes into the same `Json` value and
+//! goes through the same migrations, but it's much smaller and faster to
+//! write: numbers are varints, and every string (most of them struct field
+//! names) is written out once and referred to by index after that.
+//!
+//! A value is a tag byte followed by its payload:
+//!
+//! * `NULL`, `FALSE`, `TRUE`: nothing
+//! * `UINT`: a varint; `NEG_INT`: a varint of `!v` for a negative `v`
+//! * `FLOAT`: 8 bytes, little-endian
+//! * `STRING`: a varint length and that many bytes of UTF-8, which become
+//!   the next entry of the string table
+//! * `STRING_REF`: a varint index into the string table
+//! * `ARRAY`: a varint count and that many values
+//! * `OBJECT`: a varint count and that many key (`STRING` or `STRING_REF`)
+//!   and value pairs
+
+use std::collections::{BTreeMap, HashMap};
+
+use rustc_serialize::{Encodable, Encoder};
+use rustc_serialize::json::Json;
+
+const NULL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const UINT: u8 = 3;
+const NEG_INT: u8 = 4;
+const FLOAT: u8 = 5;
+const STRING: u8 = 6;
+const STRING_REF: u8 = 7;
+const ARRAY: u8 = 8;
+const OBJECT: u8 = 9;
+
+/// How deep arrays and objects can be nested, so a damaged save can't
+/// overflow the stack.
+const MAX_DEPTH: usize = 128;
+
+pub fn encode<T: Encodable>(value: &T) -> Result<Vec<u8>, String> {
+    let mut encoder = BinaryEncoder { out: vec![], strings: HashMap::new(), map_key: false };
+    value.encode(&mut encoder)?;
+    Ok(encoder.out)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Json, String> {
+    let mut decoder = BinaryDecoder { bytes: bytes, pos: 0, strings: vec![] };
+    let value = decoder.value(0)?;
+    if decoder.pos != bytes.len() {
+        return Err("trailing data".into());
+    }
+    Ok(value)
+}
+
+/// Writes the binary encoding, laid out the way `json::Encoder` lays out
+/// the JSON one.
+struct BinaryEncoder {
+    out: Vec<u8>,
+    strings: HashMap<String, usize>,
+    /// Whether a map key is being written. JSON object keys are strings, so
+    /// numbers are written as strings there too.
+    map_key: bool,
+}
+
+impl BinaryEncoder {
+    fn varint(&mut self, mut v: u64) {
+        while v >= 0x80 {
+            self.out.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        self.out.push(v as u8);
+    }
+
+    fn tag(&mut self, tag: u8) -> Result<(), String> {
+        if self.map_key {
+            return Err("map keys must be strings or numbers".into());
+        }
+        self.out.push(tag);
+        Ok(())
+    }
+
+    fn string(&mut self, v: &str) -> Result<(), String> {
+        if let Some(&index) = self.strings.get(v) {
+            self.out.push(STRING_REF);
+            self.varint(index as u64);
+        } else {
+            let index = self.strings.len();
+            self.strings.insert(v.into(), index);
+            self.out.push(STRING);
+            self.varint(v.len() as u64);
+            self.out.extend_from_slice(v.as_bytes());
+        }
+        Ok(())
+    }
+
+    fn uint(&mut self, v: u64) -> Result<(), String> {
+        if self.map_key {
+            return self.string(&v.to_string());
+        }
+        self.out.push(UINT);
+        self.varint(v);
+        Ok(())
+    }
+
+    fn int(&mut self, v: i64) -> Result<(), String> {
+        if v >= 0 {
+            return self.uint(v as u64);
+        }
+        if self.map_key {
+            return self.string(&v.to_string());
+        }
+        self.out.push(NEG_INT);
+        self.varint(!v as u64);
+        Ok(())
+    }
+
+    fn array<F>(&mut self, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.tag(ARRAY)?;
+        self.varint(len as u64);
+        f(self)
+    }
+}
+
+impl Encoder for BinaryEncoder {
+    type Error = String;
+
+    fn emit_nil(&mut self) -> Result<(), String> { self.tag(NULL) }
+
+    fn emit_usize(&mut self, v: usize) -> Result<(), String> { self.uint(v as u64) }
+    fn emit_u64(&mut self, v: u64) -> Result<(), String> { self.uint(v) }
+    fn emit_u32(&mut self, v: u32) -> Result<(), String> { self.uint(v as u64) }
+    fn emit_u16(&mut self, v: u16) -> Result<(), String> { self.uint(v as u64) }
+    fn emit_u8(&mut self, v: u8) -> Result<(), String> { self.uint(v as u64) }
+
+    fn emit_isize(&mut self, v: isize) -> Result<(), String> { self.int(v as i64) }
+    fn emit_i64(&mut self, v: i64) -> Result<(), String> { self.int(v) }
+    fn emit_i32(&mut self, v: i32) -> Result<(), String> { self.int(v as i64) }
+    fn emit_i16(&mut self, v: i16) -> Result<(), String> { self.int(v as i64) }
+    fn emit_i8(&mut self, v: i8) -> Result<(), String> { self.int(v as i64) }
+
+    fn emit_bool(&mut self, v: bool) -> Result<(), String> {
+        self.tag(if v { TRUE } else { FALSE })
+    }
+
+    fn emit_f64(&mut self, v: f64) -> Result<(), String> {
+        self.tag(FLOAT)?;
+        let bits = v.to_bits();
+        for i in 0..8 {
+            self.out.push((bits >> (8 * i)) as u8);
+        }
+        Ok(())
+    }
+    fn emit_f32(&mut self, v: f32) -> Result<(), String> { self.emit_f64(v as f64) }
+
+    fn emit_char(&mut self, v: char) -> Result<(), String> {
+        let mut buf = [0; 4];
+        self.string(v.encode_utf8(&mut buf))
+    }
+    fn emit_str(&mut self, v: &str) -> Result<(), String> { self.string(v) }
+
+    fn emit_enum<F>(&mut self, _name: &str, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_enum_variant<F>(&mut self, name: &str, _id: usize, cnt: usize, f: F)
+        -> Result<(), String> where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        if cnt == 0 {
+            return self.string(name);
+        }
+        self.tag(OBJECT)?;
+        self.varint(2);
+        self.string("variant")?;
+        self.string(name)?;
+        self.string("fields")?;
+        self.array(cnt, f)
+    }
+
+    fn emit_enum_variant_arg<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_enum_struct_variant<F>(&mut self, name: &str, id: usize, cnt: usize, f: F)
+        -> Result<(), String> where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.emit_enum_variant(name, id, cnt, f)
+    }
+
+    fn emit_enum_struct_variant_field<F>(&mut self, _name: &str, idx: usize, f: F)
+        -> Result<(), String> where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.emit_enum_variant_arg(idx, f)
+    }
+
+    fn emit_struct<F>(&mut self, _name: &str, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.tag(OBJECT)?;
+        self.varint(len as u64);
+        f(self)
+    }
+
+    fn emit_struct_field<F>(&mut self, name: &str, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.string(name)?;
+        f(self)
+    }
+
+    fn emit_tuple<F>(&mut self, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.array(len, f)
+    }
+
+    fn emit_tuple_arg<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_tuple_struct<F>(&mut self, _name: &str, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.array(len, f)
+    }
+
+    fn emit_tuple_struct_arg<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_option<F>(&mut self, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_option_none(&mut self) -> Result<(), String> { self.tag(NULL) }
+
+    fn emit_option_some<F>(&mut self, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_seq<F>(&mut self, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.array(len, f)
+    }
+
+    fn emit_seq_elt<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+
+    fn emit_map<F>(&mut self, len: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.tag(OBJECT)?;
+        self.varint(len as u64);
+        f(self)
+    }
+
+    fn emit_map_elt_key<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        self.map_key = true;
+        let result = f(self);
+        self.map_key = false;
+        result
+    }
+
+    fn emit_map_elt_val<F>(&mut self, _idx: usize, f: F) -> Result<(), String>
+        where F: FnOnce(&mut Self) -> Result<(), String>
+    {
+        f(self)
+    }
+}
+
+struct BinaryDecoder<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    strings: Vec<String>,
+}
+
+impl<'a> BinaryDecoder<'a> {
+    fn byte(&mut self) -> Result<u8, String> {
+        let byte = *self.bytes.get(self.pos).ok_or("unexpected end of data")?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn varint(&mut self) -> Result<u64, String> {
+        let mut v = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            v |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(v);
+            }
+        }
+        Err("number too long".into())
+    }
+
+    /// A count of things still to come, each at least a byte long.
+    fn count(&mut self) -> Result<usize, String> {
+        let count = self.varint()?;
+        if count > (self.bytes.len() - self.pos) as u64 {
+            return Err("count past the end of data".into());
+        }
+        Ok(count as usize)
+    }
+
+    fn string(&mut self, tag: u8) -> Result<String, String> {
+        match tag {
+            STRING => {
+                let len = self.count()?;
+                let bytes = &self.bytes[self.pos..self.pos + len];
+                self.pos += len;
+                let string = String::from_utf8(bytes.to_vec())
+                    .map_err(|_| "invalid UTF-8 in a string")?;
+                self.strings.push(string.clone());
+                Ok(string)
+            }
+            STRING_REF => {
+                let index = self.varint()?;
+                self.strings.get(index as usize).cloned().ok_or("unknown string".into())
+            }
+            _ => Err(format!("expected a string, found tag {}", tag)),
+        }
+    }
+
+    fn value(&mut self, depth: usize) -> Result<Json, String> {
+        if depth > MAX_DEPTH {
+            return Err("nested too deeply".into());
+        }
+        let tag = self.byte()?;
+        Ok(match tag {
+            NULL => Json::Null,
+            FALSE => Json::Boolean(false),
+            TRUE => Json::Boolean(true),
+            UINT => Json::U64(self.varint()?),
+            NEG_INT => Json::I64(!(self.varint()? as i64)),
+            FLOAT => {
+                let mut bits = 0u64;
+                for i in 0..8 {
+                    bits |= (self.byte()? as u64) << (8 * i);
+                }
+                Json::F64(f64::from_bits(bits))
+            }
+            STRING | STRING_REF => Json::String(self.string(tag)?),
+            ARRAY => {
+                let count = self.count()?;
+                let mut array = Vec::with_capacity(count);
+                for _ in 0..count {
+                    array.push(self.value(depth + 1)?);
+                }
+                Json::Array(array)
+            }
+            OBJECT => {
+                let count = self.count()?;
+                let mut object = BTreeMap::new();
+                for _ in 0..count {
+                    let tag = self.byte()?;
+                    let key = self.string(tag)?;
+                    object.insert(key, self.value(depth + 1)?);
+                }
+                Json::Object(object)
+            }
+            _ => return Err(format!("unknown tag {}", tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use flate2::Compression;
+    use flate2::read::DeflateDecoder;
+    use flate2::write::DeflateEncoder;
+    use rustc_serialize::json;
+
+    use super::*;
+    use game::{Command, GameMode, WizardCommand, World};
+
+    /// A game some turns in, with monsters, items and a level left behind.
+    fn world() -> World {
+        let mut world = World::new(3, GameMode::Exploration);
+        world.game.wizard = true;
+        world.step(Command::Wizard(WizardCommand::Descend));
+        for &(dx, dy) in [(1, 0), (0, 1), (-1, 0), (0, -1)].iter().cycle().take(40) {
+            world.step(Command::Move(dx, dy));
+        }
+        world
+    }
+
+    fn as_json<T: Encodable>(value: &T) -> Json {
+        Json::from_str(&json::encode(value).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_matches_json() {
+        let world = world();
+        assert!(!world.game.levels.is_empty());
+        let bytes = encode(&world.game).unwrap();
+        assert_eq!(decode(&bytes).unwrap(), as_json(&world.game));
+        assert_eq!(decode(&encode(&world.objects).unwrap()).unwrap(), as_json(&world.objects));
+    }
+
+    #[test]
+    fn round_trip_through_deflate_matches_json() {
+        let world = world();
+        let bytes = encode(&world.game).unwrap();
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(&bytes).unwrap();
+        let deflated = encoder.finish().unwrap();
+        assert!(deflated.len() < bytes.len());
+
+        let mut inflated = vec![];
+        DeflateDecoder::new(&deflated[..]).read_to_end(&mut inflated).unwrap();
+        assert_eq!(decode(&inflated).unwrap(), as_json(&world.game));
+    }
+
+    #[test]
+    fn values_round_trip() {
+        let value = Json::from_str(r#"{"a": [null, true, false, 0, 127, 128, 18446744073709551615,
+            -1, -9223372036854775808, 1.5, "", "é", "a", {"a": "a"}], "b": {}}"#).unwrap();
+        assert_eq!(decode(&encode(&value).unwrap()).unwrap(), value);
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let bytes = encode(&world().game).unwrap();
+        assert!(decode(&[]).is_err());
+        for len in (0..bytes.len()).step_by(bytes.len() / 100).chain(bytes.len() - 16..bytes.len()) {
+            assert!(decode(&bytes[..len]).is_err(), "decoded the first {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn corrupt_input_is_an_error() {
+        let bytes = encode(&world().game).unwrap();
+        let mut trailing = bytes.clone();
+        trailing.push(NULL);
+        assert!(decode(&trailing).is_err());
+
+        assert!(decode(&[42]).is_err());
+        assert!(decode(&[STRING_REF, 0]).is_err());
+        assert!(decode(&[STRING, 2, 0xff, 0xfe]).is_err());
+        assert!(decode(&[OBJECT, 1, UINT, 1, NULL]).is_err());
+        assert!(decode(&[ARRAY, 0xff, 0xff, 0xff, 0xff, 0x0f]).is_err());
+        assert!(decode(&[UINT, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]).is_err());
+        let nested: Vec<u8> = (0..MAX_DEPTH + 2).flat_map(|_| vec![ARRAY, 1]).chain(Some(NULL)).collect();
+        assert!(decode(&nested).is_err());
+
+        // whatever a damaged byte turns into, decoding it mustn't panic
+        for pos in (0..bytes.len()).step_by(bytes.len() / 100) {
+            for &byte in &[0, 0x7f, 0x80, 0xff] {
+                let mut corrupt = bytes.clone();
+                corrupt[pos] = byte;
+                let _ = decode(&corrupt);
+            }
+        }
+    }
+}
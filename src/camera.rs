@@ -0,0 +1,113 @@
+//! The part of the map that is on screen. Maps can be bigger than the
+//! window, so the camera follows the player around.
+
+use std::cmp;
+
+/// Which map tile is in the top-left corner of the map view, and how many
+/// tiles the view shows.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Camera {
+    pub fn new(width: i32, height: i32) -> Self {
+        Camera { x: 0, y: 0, width: width, height: height }
+    }
+
+    /// Center the view on a map position, but never show past the edges of
+    /// the map. Maps smaller than the view stay in its top-left corner.
+    pub fn follow(&mut self, (x, y): (i32, i32), (map_width, map_height): (i32, i32)) {
+        self.x = clamp(x - self.width / 2, 0, map_width - self.width);
+        self.y = clamp(y - self.height / 2, 0, map_height - self.height);
+    }
+
+    /// Where a map position is on screen, if it's in view.
+    pub fn to_screen(&self, x: i32, y: i32) -> Option<(i32, i32)> {
+        let (screen_x, screen_y) = (x - self.x, y - self.y);
+        if screen_x >= 0 && screen_y >= 0 && screen_x < self.width && screen_y < self.height {
+            Some((screen_x, screen_y))
+        } else {
+            None
+        }
+    }
+
+    /// The map position shown at a place on screen, if it's in the view.
+    /// It can still be off the map when the map is smaller than the view.
+    pub fn to_map(&self, screen_x: i32, screen_y: i32) -> Option<(i32, i32)> {
+        if screen_x >= 0 && screen_y >= 0 && screen_x < self.width && screen_y < self.height {
+            Some((screen_x + self.x, screen_y + self.y))
+        } else {
+            None
+        }
+    }
+}
+
+/// Keep a value between `min` and `max`, with `min` winning if they cross.
+fn clamp(value: i32, min: i32, max: i32) -> i32 {
+    cmp::max(min, cmp::min(value, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: (i32, i32) = (100, 50);
+
+    fn following(pos: (i32, i32), map_size: (i32, i32)) -> (i32, i32) {
+        let mut camera = Camera::new(20, 10);
+        camera.follow(pos, map_size);
+        (camera.x, camera.y)
+    }
+
+    #[test]
+    fn the_camera_centers_on_what_it_follows() {
+        assert_eq!(following((50, 25), MAP), (40, 20));
+    }
+
+    #[test]
+    fn the_camera_stops_at_the_edges_of_the_map() {
+        assert_eq!(following((0, 0), MAP), (0, 0));
+        assert_eq!(following((99, 49), MAP), (80, 40));
+        assert_eq!(following((3, 48), MAP), (0, 40));
+        assert_eq!(following((97, 2), MAP), (80, 0));
+        assert_eq!(following((5, 25), MAP), (0, 20));
+        assert_eq!(following((95, 25), MAP), (80, 20));
+        assert_eq!(following((50, 1), MAP), (40, 0));
+        assert_eq!(following((50, 47), MAP), (40, 40));
+    }
+
+    #[test]
+    fn small_maps_stay_in_the_top_left_corner() {
+        for &pos in &[(0, 0), (7, 3), (14, 5)] {
+            assert_eq!(following(pos, (15, 6)), (0, 0));
+        }
+        // smaller one way only
+        assert_eq!(following((90, 3), (100, 6)), (80, 0));
+    }
+
+    #[test]
+    fn screen_and_map_positions_convert_both_ways() {
+        let mut camera = Camera::new(20, 10);
+        camera.follow((60, 30), MAP);
+        for x in 0 .. MAP.0 {
+            for y in 0 .. MAP.1 {
+                match camera.to_screen(x, y) {
+                    Some((screen_x, screen_y)) => assert_eq!(camera.to_map(screen_x, screen_y), Some((x, y))),
+                    None => assert!(x < camera.x || y < camera.y || x >= camera.x + 20 || y >= camera.y + 10),
+                }
+            }
+        }
+        for screen_x in 0 .. 20 {
+            for screen_y in 0 .. 10 {
+                let (x, y) = camera.to_map(screen_x, screen_y).unwrap();
+                assert_eq!(camera.to_screen(x, y), Some((screen_x, screen_y)));
+            }
+        }
+        assert_eq!(camera.to_map(20, 0), None);
+        assert_eq!(camera.to_map(0, -1), None);
+    }
+}
@@ -0,0 +1,217 @@
+//! Command-line options of the libtcod front-end.
+
+use std::cmp;
+use std::path::PathBuf;
+
+use roguelike::{GameMode, SaveFormat, BOSS_DEPTH, FINAL_DEPTH, MAP_HEIGHT, MAP_WIDTH};
+
+/// The smallest levels the generators can make.
+const MIN_MAP_SIZE: (i32, i32) = (30, 20);
+
+pub const USAGE: &'static str = "Usage: roguelike [OPTIONS]
+
+Options:
+    --seed N          Start new games from this seed
+    --new             Start a new game right away, skipping the menu
+    --continue        Continue the saved game right away, skipping the menu
+    --save-dir DIR    Where the save slots are kept (default: saves)
+    --save-file PATH  Save to and continue from this file instead of a slot
+    --save-format F   Save as json, binary or compressed (default: compressed)
+    --vaults DIR      Where the vault files are kept (default: vaults)
+    --font PATH       The font image to use (default: arial10x10.png)
+    --size WxH        The window size in characters (default: 80x50)
+    --map-size WxH    The size of new levels, at least 30x20 (default: 80x43)
+    --final-depth N   Put the Amulet of the Ancient Kings on this depth (default: 10)
+    --boss-depth N    Put the boss's lair on this depth, from 2 up to the final depth
+                      (default: 5, or the final depth if that's less)
+    --exploration     Keep the save of new games when dying (default: permadeath)
+    --wizard          Enable wizard (debug) commands
+    --headless        Play without a window, reading commands from stdin
+    --help            Show this message";
+
+/// How to start once the window is open.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Start {
+    Menu,
+    NewGame,
+    Continue,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub seed: Option<u64>,
+    pub start: Start,
+    pub save_dir: PathBuf,
+    pub save_file: Option<PathBuf>,
+    pub save_format: SaveFormat,
+    pub vault_dir: PathBuf,
+    pub font: PathBuf,
+    pub size: (i32, i32),
+    pub map_size: (i32, i32),
+    pub final_depth: u32,
+    pub boss_depth: u32,
+    pub mode: GameMode,
+    pub wizard: bool,
+    pub headless: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            seed: None,
+            start: Start::Menu,
+            save_dir: PathBuf::from("saves"),
+            save_file: None,
+            save_format: SaveFormat::default(),
+            vault_dir: PathBuf::from("vaults"),
+            font: PathBuf::from("arial10x10.png"),
+            size: (80, 50),
+            map_size: (MAP_WIDTH, MAP_HEIGHT),
+            final_depth: FINAL_DEPTH,
+            boss_depth: BOSS_DEPTH,
+            mode: GameMode::Permadeath,
+            wizard: false,
+            headless: false,
+            help: false,
+        }
+    }
+}
+
+/// Parse the arguments, without the program name.
+pub fn parse_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut boss_depth = None;
+    let mut args = args;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                let seed = value(&arg, args.next())?;
+                options.seed = Some(seed.parse()
+                    .map_err(|_| format!("The seed must be a number, not '{}'.", seed))?);
+            }
+            "--new" | "--continue" => {
+                if options.start != Start::Menu {
+                    return Err("Only one of --new and --continue can be given.".into());
+                }
+                options.start = if arg == "--new" { Start::NewGame } else { Start::Continue };
+            }
+            "--save-dir" => options.save_dir = PathBuf::from(value(&arg, args.next())?),
+            "--save-file" => options.save_file = Some(PathBuf::from(value(&arg, args.next())?)),
+            "--save-format" => options.save_format = value(&arg, args.next())?.parse()?,
+            "--vaults" => options.vault_dir = PathBuf::from(value(&arg, args.next())?),
+            "--font" => options.font = PathBuf::from(value(&arg, args.next())?),
+            "--size" => options.size = parse_size(&value(&arg, args.next())?)?,
+            "--map-size" => {
+                let (width, height) = parse_size(&value(&arg, args.next())?)?;
+                if width < MIN_MAP_SIZE.0 || height < MIN_MAP_SIZE.1 {
+                    return Err(format!("The map must be at least {}x{}, not {}x{}.",
+                                       MIN_MAP_SIZE.0, MIN_MAP_SIZE.1, width, height));
+                }
+                options.map_size = (width, height);
+            }
+            "--final-depth" => {
+                let depth = value(&arg, args.next())?;
+                options.final_depth = match depth.parse() {
+                    Ok(depth) if depth >= 2 => depth,
+                    _ => return Err(format!("The final depth must be a number from 2 up, not '{}'.",
+                                            depth)),
+                };
+            }
+            "--boss-depth" => {
+                let depth = value(&arg, args.next())?;
+                boss_depth = match depth.parse() {
+                    Ok(depth) if depth >= 2 => Some(depth),
+                    _ => return Err(format!("The boss depth must be a number from 2 up, not '{}'.",
+                                            depth)),
+                };
+            }
+            "--exploration" => options.mode = GameMode::Exploration,
+            "--wizard" => options.wizard = true,
+            "--headless" => options.headless = true,
+            "--help" | "-h" => options.help = true,
+            _ => return Err(format!("Unknown option '{}'.", arg)),
+        }
+    }
+    // the boss's lair has to be on the way to the amulet
+    options.boss_depth = match boss_depth {
+        Some(depth) if depth > options.final_depth => {
+            return Err(format!("The boss depth can't be below the final depth, {}, but it's {}.",
+                               options.final_depth, depth));
+        }
+        Some(depth) => depth,
+        None => cmp::min(options.boss_depth, options.final_depth),
+    };
+    Ok(options)
+}
+
+fn value(option: &str, value: Option<String>) -> Result<String, String> {
+    value.ok_or(format!("{} needs a value.", option))
+}
+
+fn parse_size(size: &str) -> Result<(i32, i32), String> {
+    let invalid = || format!("The size must look like 80x50, not '{}'.", size);
+    let mut parts = size.splitn(2, 'x');
+    let width = parts.next().and_then(|w| w.parse().ok()).ok_or_else(&invalid)?;
+    let height = parts.next().and_then(|h| h.parse().ok()).ok_or_else(&invalid)?;
+    Ok((width, height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_options_give_the_defaults() {
+        assert_eq!(parse(&[]), Ok(Options::default()));
+    }
+
+    #[test]
+    fn options_are_parsed() {
+        let options = parse(&["--seed", "42", "--new", "--save-file", "game.sav", "--save-format", "json",
+                              "--size", "100x60", "--map-size", "120x80", "--final-depth", "12",
+                              "--boss-depth", "12", "--exploration", "--wizard", "--headless"]).unwrap();
+        assert_eq!(options.seed, Some(42));
+        assert_eq!(options.start, Start::NewGame);
+        assert_eq!(options.save_file, Some(PathBuf::from("game.sav")));
+        assert_eq!(options.save_format, SaveFormat::Json);
+        assert_eq!(options.size, (100, 60));
+        assert_eq!(options.map_size, (120, 80));
+        assert_eq!(options.final_depth, 12);
+        assert_eq!(options.boss_depth, 12);
+        assert_eq!(options.mode, GameMode::Exploration);
+        assert!(options.wizard && options.headless && !options.help);
+        assert_eq!(parse(&["--continue"]).unwrap().start, Start::Continue);
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn bad_options_are_rejected() {
+        assert_eq!(parse(&["--fly"]), Err("Unknown option '--fly'.".into()));
+        assert_eq!(parse(&["--seed"]), Err("--seed needs a value.".into()));
+        assert_eq!(parse(&["--seed", "x"]), Err("The seed must be a number, not 'x'.".into()));
+        assert_eq!(parse(&["--new", "--continue"]), Err("Only one of --new and --continue can be given.".into()));
+        assert_eq!(parse(&["--size", "80"]), Err("The size must look like 80x50, not '80'.".into()));
+        assert_eq!(parse(&["--map-size", "20x20"]), Err("The map must be at least 30x20, not 20x20.".into()));
+        assert_eq!(parse(&["--final-depth", "1"]),
+                   Err("The final depth must be a number from 2 up, not '1'.".into()));
+        assert_eq!(parse(&["--boss-depth", "one"]),
+                   Err("The boss depth must be a number from 2 up, not 'one'.".into()));
+    }
+
+    #[test]
+    fn the_boss_is_on_the_way_to_the_amulet() {
+        let error = Err("The boss depth can't be below the final depth, 4, but it's 6.".into());
+        assert_eq!(parse(&["--final-depth", "4", "--boss-depth", "6"]), error);
+        assert_eq!(parse(&["--boss-depth", "6", "--final-depth", "4"]), error);
+        assert_eq!(parse(&["--boss-depth", "12"]).map(|options| options.boss_depth),
+                   Err("The boss depth can't be below the final depth, 10, but it's 12.".into()));
+        // the default moves up for short games
+        assert_eq!(parse(&["--final-depth", "3"]).unwrap().boss_depth, 3);
+        assert_eq!(parse(&["--final-depth", "3", "--boss-depth", "2"]).unwrap().boss_depth, 2);
+    }
+}
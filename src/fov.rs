@@ -0,0 +1,138 @@
+//! Field of view, computed on the game's own grid so it can run without a
+//! libtcod window. The API follows `tcod::map::Map` closely.
+
+/// Transparency and walkability of every cell, plus the result of the last
+/// field of view computation.
+pub struct FovMap {
+    width: i32,
+    height: i32,
+    transparent: Vec<bool>,
+    walkable: Vec<bool>,
+    in_fov: Vec<bool>,
+}
+
+impl FovMap {
+    pub fn new(width: i32, height: i32) -> Self {
+        let size = (width * height) as usize;
+        FovMap {
+            width: width,
+            height: height,
+            transparent: vec![false; size],
+            walkable: vec![false; size],
+            in_fov: vec![false; size],
+        }
+    }
+
+    pub fn size(&self) -> (i32, i32) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, x: i32, y: i32) -> Option<usize> {
+        if x >= 0 && y >= 0 && x < self.width && y < self.height {
+            Some((y * self.width + x) as usize)
+        } else {
+            None
+        }
+    }
+
+    pub fn set(&mut self, x: i32, y: i32, transparent: bool, walkable: bool) {
+        if let Some(index) = self.index(x, y) {
+            self.transparent[index] = transparent;
+            self.walkable[index] = walkable;
+        }
+    }
+
+    pub fn is_transparent(&self, x: i32, y: i32) -> bool {
+        self.index(x, y).map_or(false, |index| self.transparent[index])
+    }
+
+    pub fn is_walkable(&self, x: i32, y: i32) -> bool {
+        self.index(x, y).map_or(false, |index| self.walkable[index])
+    }
+
+    pub fn is_in_fov(&self, x: i32, y: i32) -> bool {
+        self.index(x, y).map_or(false, |index| self.in_fov[index])
+    }
+
+    /// Cast a ray from the origin to every cell on the border of the
+    /// radius' bounding box, lighting cells until a ray hits something
+    /// opaque. A radius of 0 means unlimited.
+    pub fn compute_fov(&mut self, origin_x: i32, origin_y: i32, radius: i32, light_walls: bool) {
+        for cell in self.in_fov.iter_mut() {
+            *cell = false;
+        }
+        if self.index(origin_x, origin_y).is_none() {
+            return;
+        }
+
+        let (min_x, max_x, min_y, max_y) = if radius > 0 {
+            (origin_x - radius, origin_x + radius, origin_y - radius, origin_y + radius)
+        } else {
+            (0, self.width - 1, 0, self.height - 1)
+        };
+
+        let mut border = vec![];
+        for x in min_x .. max_x + 1 {
+            border.push((x, min_y));
+            border.push((x, max_y));
+        }
+        for y in min_y + 1 .. max_y {
+            border.push((min_x, y));
+            border.push((max_x, y));
+        }
+
+        let index = self.index(origin_x, origin_y).unwrap();
+        self.in_fov[index] = true;
+        for (x, y) in border {
+            self.cast_ray(origin_x, origin_y, x, y, radius, light_walls);
+        }
+    }
+
+    fn cast_ray(&mut self, origin_x: i32, origin_y: i32, x: i32, y: i32,
+            radius: i32, light_walls: bool) {
+        let radius_squared = radius * radius;
+        for (cx, cy) in line((origin_x, origin_y), (x, y)).into_iter().skip(1) {
+            let index = match self.index(cx, cy) {
+                Some(index) => index,
+                None => return,
+            };
+            let (dx, dy) = (cx - origin_x, cy - origin_y);
+            if radius > 0 && dx * dx + dy * dy > radius_squared {
+                return;
+            }
+            if self.transparent[index] {
+                self.in_fov[index] = true;
+            } else {
+                if light_walls {
+                    self.in_fov[index] = true;
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// All the cells on a Bresenham line from `start` to `end`, both included.
+pub fn line(start: (i32, i32), end: (i32, i32)) -> Vec<(i32, i32)> {
+    let (mut x, mut y) = start;
+    let dx = (end.0 - x).abs();
+    let dy = -(end.1 - y).abs();
+    let step_x = if x < end.0 { 1 } else { -1 };
+    let step_y = if y < end.1 { 1 } else { -1 };
+    let mut error = dx + dy;
+
+    let mut cells = vec![(x, y)];
+    while (x, y) != end {
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+        cells.push((x, y));
+    }
+    cells
+}
@@ -0,0 +1,1923 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::mem;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use colors;
+use fov::{line, FovMap};
+use map::{flood_fill, is_blocked, make_map, map_size, place_artifact, Door, Map, Terrain, Trap, TrapKind};
+use themes::Theme;
+use rng::GameRng;
+use save::{delete_save, save_game, SaveFormat};
+use vaults::Vault;
+use pathfinding::{find_path, safety_map};
+use object::{get_equipped_in_slot, mut_two, Ai, DeathCallback, Equipment, Fighter, Item,
+             MessageLog, Messages, Object, Projectile, Slot};
+use {ALARM_NUM_TURNS, ALARM_RADIUS, ASLEEP_PERCEPTION_DIVISOR, BOSS_DEPTH, CONFUSE_NUM_TURNS, CONFUSE_RANGE,
+     DART_DAMAGE, FIREBALL_DAMAGE, FIREBALL_RADIUS, FLEE_REGEN_TURNS, FLEE_TURNS, FOV_LIGHT_WALLS, FINAL_DEPTH,
+     FREE_SPOT_TRIES, HEAL_AMOUNT, KEEP_AWAY_DISTANCE, LEVEL_UP_BASE, LEVEL_UP_FACTOR, LIGHTNING_DAMAGE,
+     LIGHTNING_RANGE, MAP_HEIGHT, MAP_WIDTH, MONSTER_SEARCH_RADIUS, MONSTER_SEARCH_TURNS, NOTICE_CHANCE_PER_AGILITY,
+     PACK_BREAK_TURNS, PIT_DAMAGE, SEARCH_CHANCE, SEARCH_RADIUS, SEARCH_TURNS, TORCH_RADIUS, VICTORY_BONUS};
+
+pub const PLAYER: usize = 0;
+
+/// The eight directions to move in, going round clockwise.
+const DIRECTIONS: [(i32, i32); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Game {
+    pub map: Map,
+    /// The theme of the level the player is on.
+    pub theme: Theme,
+    pub log: Messages,
+    pub inventory: Vec<Object>,
+    pub dungeon_level: u32,
+    /// The levels the player has left, by depth, kept as they were left.
+    pub levels: BTreeMap<u32, Level>,
+    /// The depth the Amulet of the Ancient Kings lies on.
+    pub final_depth: u32,
+    /// The depth of the boss's lair.
+    pub boss_depth: u32,
+    /// Whether the player escaped with the amulet.
+    pub won: bool,
+    /// How many turns the player has taken.
+    pub turns: u32,
+    /// How many monsters the player has killed.
+    pub kills: u32,
+    /// The seed the game was started with, to replay the same dungeon.
+    pub seed: u64,
+    pub rng: GameRng,
+    /// Whether wizard (debug) commands are allowed in this game.
+    pub wizard: bool,
+    pub mode: GameMode,
+}
+
+/// A level of the dungeon the player isn't on.
+#[derive(RustcEncodable, RustcDecodable)]
+pub struct Level {
+    pub map: Map,
+    pub theme: Theme,
+    /// Everything on the level but the player.
+    pub objects: Vec<Object>,
+}
+
+/// How saves are treated over the course of a game.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum GameMode {
+    /// The save is consumed when the game is loaded, written back only when
+    /// the game is left or autosaved, and deleted when the player dies.
+    Permadeath,
+    /// The save stays around, so a game can be reloaded at will.
+    Exploration,
+}
+
+/// A stat the player can raise when levelling up.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stat {
+    Constitution,
+    Strength,
+    Agility,
+}
+
+/// Everything the player can do. The world advances by one command at a time.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Command {
+    /// Move in a direction, attacking whatever is in the way.
+    Move(i32, i32),
+    Wait,
+    PickUp,
+    /// Use an inventory item. Items whose `targeting()` isn't `None` need a target tile.
+    UseItem { inventory_id: usize, target: Option<(i32, i32)> },
+    DropItem(usize),
+    /// Close the open door in a direction.
+    CloseDoor(i32, i32),
+    /// Look for hidden traps and secret doors around the player, for a few turns.
+    Search,
+    TakeStairs,
+    LevelUp(Stat),
+    /// Debug commands, only available in wizard mode.
+    Wizard(WizardCommand),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WizardCommand {
+    /// Mark the whole level as explored.
+    RevealMap,
+    /// Go down a level, wherever the player stands.
+    Descend,
+    /// Restore the player to full health.
+    Heal,
+}
+
+/// Parse the text form of a command, as used by scripted runs:
+/// `move DX DY`, `wait`, `pickup`, `use N [X Y]`, `drop N`, `close DX DY`, `search`, `stairs`,
+/// `levelup con|str|agi` and `wizard reveal|descend|heal`. Moves and doors are a step away, so
+/// DX and DY go from -1 to 1.
+impl FromStr for Command {
+    type Err = String;
+
+    fn from_str(line: &str) -> Result<Self, String> {
+        fn number<T: FromStr>(word: Option<&str>) -> Result<T, String> {
+            let word = word.ok_or("Missing argument.".to_string())?;
+            word.parse().map_err(|_| format!("'{}' is not a number.", word))
+        }
+        fn step(word: Option<&str>) -> Result<i32, String> {
+            let step: i32 = number(word)?;
+            if step.abs() <= 1 {
+                Ok(step)
+            } else {
+                Err(format!("Steps go from -1 to 1, not {}.", step))
+            }
+        }
+
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some("move") => Command::Move(step(words.next())?, step(words.next())?),
+            Some("wait") => Command::Wait,
+            Some("pickup") => Command::PickUp,
+            Some("use") => {
+                let inventory_id = number(words.next())?;
+                let target = match words.next() {
+                    Some(x) => Some((number(Some(x))?, number(words.next())?)),
+                    None => None,
+                };
+                Command::UseItem { inventory_id: inventory_id, target: target }
+            }
+            Some("drop") => Command::DropItem(number(words.next())?),
+            Some("close") => Command::CloseDoor(step(words.next())?, step(words.next())?),
+            Some("search") => Command::Search,
+            Some("stairs") => Command::TakeStairs,
+            Some("levelup") => Command::LevelUp(match words.next() {
+                Some("con") => Stat::Constitution,
+                Some("str") => Stat::Strength,
+                Some("agi") => Stat::Agility,
+                _ => return Err("Level up needs one of con, str or agi.".into()),
+            }),
+            Some("wizard") => Command::Wizard(match words.next() {
+                Some("reveal") => WizardCommand::RevealMap,
+                Some("descend") => WizardCommand::Descend,
+                Some("heal") => WizardCommand::Heal,
+                _ => return Err("Wizard needs one of reveal, descend or heal.".into()),
+            }),
+            Some(other) => return Err(format!("Unknown command '{}'.", other)),
+            None => return Err("Empty command.".into()),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("Unexpected '{}' after the command.", extra)),
+            None => Ok(command),
+        }
+    }
+}
+
+/// What happened while a command was carried out.
+#[derive(Debug)]
+pub struct Turn {
+    /// Whether the command used up the player's turn, letting the monsters act.
+    pub took_turn: bool,
+    /// The messages added to the log during this turn.
+    pub messages: Messages,
+}
+
+enum UseResult {
+    UsedUp,
+    Cancelled,
+    UsedAndKept,
+}
+
+/// The complete state of a running game, independent of any front-end.
+pub struct World {
+    pub objects: Vec<Object>,
+    pub game: Game,
+    pub fov: FovMap,
+    /// Where the game is autosaved whenever the player changes level.
+    pub save_path: Option<PathBuf>,
+    pub save_format: SaveFormat,
+    /// The vaults new levels can have. They aren't part of the save.
+    pub vaults: Vec<Vault>,
+}
+
+impl World {
+    pub fn new(seed: u64, mode: GameMode) -> Self {
+        World::generate(seed, mode, (MAP_WIDTH, MAP_HEIGHT), vec![])
+    }
+
+    /// Start a game whose levels are `map_size` tiles big and can have the
+    /// given vaults.
+    pub fn generate(seed: u64, mode: GameMode, map_size: (i32, i32), vaults: Vec<Vault>) -> Self {
+        let mut rng = GameRng::new(seed);
+        let mut objects = vec![];
+
+        let mut player = Object::new(0, 0, '@', "player", colors::WHITE, true);
+        player.alive = true;
+        player.fighter = Some(
+            Fighter { base_max_hp: 100, hp: 100, base_defense: 1,
+                base_power: 2, on_death: DeathCallback::Player, xp: 0 });
+        objects.insert(0 as usize, player);
+
+        let theme = Theme::for_depth(1, BOSS_DEPTH, &mut rng);
+        let map = make_map(&mut objects, 1, theme, map_size, &vaults, &mut rng);
+
+        let mut game = Game {
+            map: map,
+            theme: theme,
+            log: vec![],
+            inventory: vec![],
+            dungeon_level: 1,
+            levels: BTreeMap::new(),
+            final_depth: FINAL_DEPTH,
+            boss_depth: BOSS_DEPTH,
+            won: false,
+            turns: 0,
+            kills: 0,
+            seed: seed,
+            rng: rng,
+            wizard: false,
+            mode: mode,
+        };
+
+        let mut dagger = Object::new(0, 0, '-', "dagger", colors::SKY, false);
+        dagger.item = Some(Item::Sword);
+        dagger.equipment = Some(Equipment {
+            equipped: true,
+            slot: Slot::LeftHand,
+            max_hp_bonus: 0,
+            defense_bonus: 0,
+            power_bonus: 2
+        });
+        game.inventory.push(dagger);
+
+        game.log.add("Welcome stranger! Prepare to perish in the Tombs of the Ancient Kings.",
+            colors::RED);
+
+        let mut world = World::from_parts(objects, game);
+        world.vaults = vaults;
+        world
+    }
+
+    /// Wrap already existing state, e.g. a loaded save.
+    pub fn from_parts(objects: Vec<Object>, game: Game) -> Self {
+        let (width, height) = map_size(&game.map);
+        let mut world = World {
+            objects: objects,
+            game: game,
+            fov: FovMap::new(width, height),
+            save_path: None,
+            save_format: SaveFormat::default(),
+            vaults: vec![],
+        };
+        world.initialize_fov();
+        world
+    }
+
+    pub fn player(&self) -> &Object {
+        &self.objects[PLAYER]
+    }
+
+    pub fn player_alive(&self) -> bool {
+        self.objects[PLAYER].alive
+    }
+
+    pub fn player_on_stairs(&self) -> bool {
+        self.player_on("down stairs") || self.player_on("up stairs")
+    }
+
+    /// The deepest level the player has been on.
+    pub fn deepest_level(&self) -> u32 {
+        self.game.levels.keys().cloned()
+            .chain(Some(self.game.dungeon_level))
+            .max()
+            .unwrap_or(1)
+    }
+
+    /// All the experience the player has gained, including what was spent
+    /// on levelling up.
+    pub fn total_xp(&self) -> i32 {
+        let player = &self.objects[PLAYER];
+        let spent: i32 = (1..player.level).map(|level| LEVEL_UP_BASE + level * LEVEL_UP_FACTOR).sum();
+        spent + player.fighter.map_or(0, |f| f.xp)
+    }
+
+    /// The score of the game so far: how deep the player went, how
+    /// experienced they got and how many monsters they killed. Winning is
+    /// worth a bonus, the more so the fewer turns it took.
+    pub fn score(&self) -> u32 {
+        let mut score = self.deepest_level() * 100 + self.objects[PLAYER].level as u32 * 50 +
+            self.total_xp() as u32 + self.game.kills * 10;
+        if self.game.won {
+            score += VICTORY_BONUS + VICTORY_BONUS.saturating_sub(self.game.turns / 10);
+        }
+        score
+    }
+
+    fn player_on(&self, name: &str) -> bool {
+        let player_pos = self.objects[PLAYER].pos();
+        self.objects.iter().any(|object| {
+            object.pos() == player_pos && object.name == name
+        })
+    }
+
+    /// The experience the player needs to reach the next level.
+    pub fn level_up_xp(&self) -> i32 {
+        LEVEL_UP_BASE + self.objects[PLAYER].level * LEVEL_UP_FACTOR
+    }
+
+    /// Whether the player has enough experience to level up. The front-end
+    /// should answer this with `Command::LevelUp`.
+    pub fn level_up_pending(&self) -> bool {
+        self.objects[PLAYER].fighter.as_ref().map_or(0, |f| f.xp) >= self.level_up_xp()
+    }
+
+    /// The monster standing on a tile, if any.
+    pub fn monster_at(&self, x: i32, y: i32) -> Option<usize> {
+        self.objects.iter().enumerate().position(|(id, obj)| {
+            obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER
+        })
+    }
+
+    /// The directions of the open doors next to the player.
+    pub fn open_doors_around_player(&self) -> Vec<(i32, i32)> {
+        let (x, y) = self.objects[PLAYER].pos();
+        let mut doors = vec![];
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let tile = self.game.map.get((x + dx) as usize).and_then(|column| column.get((y + dy) as usize));
+                if tile.map_or(false, |tile| tile.door == Some(Door::Open)) {
+                    doors.push((dx, dy));
+                }
+            }
+        }
+        doors
+    }
+
+    /// Carry out a player command, then let the monsters act if it took a turn.
+    /// Moves and doors more than a step away are ignored.
+    pub fn step(&mut self, command: Command) -> Turn {
+        let log_start = self.game.log.len();
+
+        let was_alive = self.player_alive();
+        let start = (self.game.dungeon_level, self.objects[PLAYER].pos());
+        let took_turn = if was_alive && !self.game.won {
+            self.player_act(command)
+        } else {
+            false
+        };
+        // doors may have opened or closed
+        self.initialize_fov();
+
+        // wading and climbing over rubble take the player more than a turn,
+        // and the monsters get to act all the while
+        let (player_x, player_y) = self.objects[PLAYER].pos();
+        let moved = self.game.dungeon_level == start.0 && (player_x, player_y) != start.1;
+        let turns = match (took_turn, command, moved) {
+            (false, _, _) => 0,
+            (true, Command::Search, _) => SEARCH_TURNS,
+            (true, _, false) => 1,
+            (true, _, true) => self.game.map[player_x as usize][player_y as usize].terrain.movement_cost(),
+        };
+        for _ in 0 .. turns {
+            self.game.turns += 1;
+            if !self.player_alive() {
+                break;
+            }
+            for id in 0 .. self.objects.len() {
+                if self.objects[id].ai.is_some() {
+                    ai_take_turn(id, &mut self.objects, &self.fov, &mut self.game);
+                }
+            }
+            burn(&mut self.objects, &mut self.game, &self.fov);
+            notice(&self.objects, &mut self.game);
+            // the monsters may have opened doors
+            self.initialize_fov();
+        }
+
+        if was_alive && !self.player_alive() && self.game.mode == GameMode::Permadeath {
+            // in permadeath games, death is final: the save goes away with the player
+            self.forget_save();
+        }
+
+        Turn {
+            took_turn: took_turn,
+            messages: self.game.log[log_start..].to_vec(),
+        }
+    }
+
+    fn player_act(&mut self, command: Command) -> bool {
+        match command {
+            Command::Move(0, 0) | Command::Wait => true,
+            Command::Move(dx, dy) | Command::CloseDoor(dx, dy) if dx.abs() > 1 || dy.abs() > 1 => false,
+            Command::Move(dx, dy) => {
+                let sprung = player_move_or_attack(dx, dy, &mut self.objects, &mut self.game, &self.fov);
+                let (x, y) = self.objects[PLAYER].pos();
+                let in_chasm = self.game.map[x as usize][y as usize].terrain == Terrain::Chasm;
+                if in_chasm {
+                    self.game.log.add("You jump into the chasm!", colors::ORANGE);
+                    self.game.log.add(format!("The fall hurts the {} for {} hit points.",
+                        self.objects[PLAYER].name, PIT_DAMAGE), colors::RED);
+                    self.objects[PLAYER].take_damage_from_dungeon(PIT_DAMAGE, &mut self.game);
+                }
+                if (sprung == Some(TrapKind::Pit) || in_chasm) && self.player_alive() {
+                    self.fall();
+                }
+                true
+            }
+            Command::PickUp => {
+                let player_pos = self.objects[PLAYER].pos();
+                let item_id = self.objects.iter().position(|object| {
+                    object.pos() == player_pos && object.item.is_some()
+                });
+                if let Some(item_id) = item_id {
+                    pick_item_up(item_id, &mut self.objects, &mut self.game);
+                }
+                true
+            }
+            Command::UseItem { inventory_id, target } => {
+                if inventory_id < self.game.inventory.len() {
+                    use_item(inventory_id, target, &mut self.objects, &mut self.game, &self.fov);
+                }
+                false
+            }
+            Command::DropItem(inventory_id) => {
+                if inventory_id < self.game.inventory.len() {
+                    drop_item(inventory_id, &mut self.objects, &mut self.game);
+                }
+                true
+            }
+            Command::CloseDoor(dx, dy) => {
+                let (x, y) = self.objects[PLAYER].pos();
+                close_door(x + dx, y + dy, &self.objects, &mut self.game)
+            }
+            Command::Search => {
+                search(&self.objects, &mut self.game);
+                true
+            }
+            Command::TakeStairs => {
+                if self.player_on("down stairs") {
+                    self.next_level();
+                } else if self.player_on("up stairs") {
+                    self.previous_level();
+                }
+                false
+            }
+            Command::LevelUp(stat) => {
+                self.level_up(stat);
+                false
+            }
+            Command::Wizard(command) => {
+                if self.game.wizard {
+                    self.wizard(command);
+                }
+                false
+            }
+        }
+    }
+
+    fn next_level(&mut self) {
+        let depth = self.game.dungeon_level + 1;
+        if self.game.levels.contains_key(&depth) {
+            self.game.log.add("You descend the stairs again.", colors::VIOLET);
+        } else {
+            self.game.log.add("You take a moment to rest, and recover your strength.", colors::VIOLET);
+            let heal_hp = self.objects[PLAYER].max_hp(&self.game) / 2;
+            self.objects[PLAYER].heal(heal_hp, &self.game);
+
+            self.game.log.add("After a rare moment of peace, you descend deeper into the heart of the dungeon...",
+                colors::RED);
+        }
+        self.change_level(depth, Some("up stairs"));
+    }
+
+    fn previous_level(&mut self) {
+        if self.game.dungeon_level == 1 {
+            if self.game.inventory.iter().any(|item| item.item == Some(Item::Artifact)) {
+                self.win();
+            } else {
+                self.game.log.add("You can't leave without the Amulet of the Ancient Kings.",
+                    colors::LIGHT_GREY);
+            }
+            return;
+        }
+        self.game.log.add("You climb back up the stairs.", colors::VIOLET);
+        let depth = self.game.dungeon_level - 1;
+        self.change_level(depth, Some("down stairs"));
+    }
+
+    /// Drop through a pit or a chasm to the level below. There's nothing
+    /// below the final depth, so pits there are just holes in the floor.
+    fn fall(&mut self) {
+        if self.game.dungeon_level < self.game.final_depth {
+            self.game.log.add("You fall through to the level below!", colors::VIOLET);
+            let depth = self.game.dungeon_level + 1;
+            self.change_level(depth, None);
+        }
+    }
+
+    /// Leave the current level as it is and go to another depth, arriving
+    /// on its `arrival` stairs, or anywhere if there are none. Depths not
+    /// visited yet are generated.
+    fn change_level(&mut self, depth: u32, arrival: Option<&str>) {
+        let objects = self.objects.split_off(PLAYER + 1);
+        // all the levels of a game are the same size
+        let size = map_size(&self.game.map);
+        let map = mem::replace(&mut self.game.map, vec![]);
+        let level = Level { map: map, theme: self.game.theme, objects: objects };
+        self.game.levels.insert(self.game.dungeon_level, level);
+
+        self.game.dungeon_level = depth;
+        match self.game.levels.remove(&depth) {
+            Some(level) => {
+                self.game.map = level.map;
+                self.game.theme = level.theme;
+                self.objects.extend(level.objects);
+            }
+            None => {
+                let theme = Theme::for_depth(depth, self.game.boss_depth, &mut self.game.rng);
+                self.game.map = make_map(&mut self.objects, depth, theme, size, &self.vaults, &mut self.game.rng);
+                self.game.theme = theme;
+                if depth == self.game.final_depth {
+                    place_artifact(&mut self.objects);
+                }
+                if let Some(message) = theme.arrival_message() {
+                    self.game.log.add(message, colors::LIGHT_GREY);
+                }
+            }
+        }
+        // the player is still where they were on the level they left
+        let arrival = match arrival {
+            Some(stairs) => self.objects.iter().find(|object| object.name == stairs)
+                .and_then(|stairs| nearest_free_spot(stairs.pos(), &self.objects[PLAYER + 1 ..], &self.game)),
+            None => random_free_spot(&self.objects[PLAYER + 1 ..], &mut self.game),
+        };
+        if let Some((x, y)) = arrival {
+            self.objects[PLAYER].set_pos(x, y);
+        }
+        self.initialize_fov();
+        self.autosave();
+    }
+
+    /// Escaping with the amulet ends the game, so there is nothing left to
+    /// save in any mode.
+    fn win(&mut self) {
+        self.game.won = true;
+        self.game.log.add("You escape the Tombs of the Ancient Kings with their amulet. You won!",
+            colors::LIGHT_GREEN);
+        self.forget_save();
+    }
+
+    fn forget_save(&mut self) {
+        if let Some(path) = self.save_path.take() {
+            if let Err(e) = delete_save(&path) {
+                self.game.log.add(format!("Could not delete the save: {}", e), colors::RED);
+            }
+        }
+    }
+
+    fn autosave(&mut self) {
+        if let Some(path) = self.save_path.clone() {
+            if let Err(e) = save_game(self, &path) {
+                self.game.log.add(format!("Autosave failed: {}", e), colors::RED);
+            }
+        }
+    }
+
+    fn wizard(&mut self, command: WizardCommand) {
+        match command {
+            WizardCommand::RevealMap => {
+                let (width, height) = map_size(&self.game.map);
+                for x in 0 .. width {
+                    for y in 0 .. height {
+                        self.game.map[x as usize][y as usize].explored = true;
+                        reveal(x, y, &mut self.game);
+                    }
+                }
+            }
+            WizardCommand::Descend => self.next_level(),
+            WizardCommand::Heal => {
+                let max_hp = self.objects[PLAYER].max_hp(&self.game);
+                self.objects[PLAYER].heal(max_hp, &self.game);
+            }
+        }
+    }
+
+    fn level_up(&mut self, stat: Stat) {
+        if !self.level_up_pending() {
+            return;
+        }
+        let level_up_xp = self.level_up_xp();
+        let player = &mut self.objects[PLAYER];
+        player.level += 1;
+        self.game.log.add(format!("Your battle skills grow stronger! You reached level {}!", player.level),
+            colors::YELLOW);
+
+        let fighter = player.fighter.as_mut().unwrap();
+        fighter.xp -= level_up_xp;
+        match stat {
+            Stat::Constitution => {
+                fighter.base_max_hp += 20;
+                fighter.hp += 20;
+            }
+            Stat::Strength => {
+                fighter.base_power += 1;
+            }
+            Stat::Agility => {
+                fighter.base_defense += 1;
+            }
+        }
+    }
+
+    fn initialize_fov(&mut self) {
+        let (width, height) = map_size(&self.game.map);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                self.fov.set(x, y,
+                    !self.game.map[x as usize][y as usize].block_sight,
+                    !self.game.map[x as usize][y as usize].blocked);
+            }
+        }
+        self.recompute_fov();
+    }
+
+    /// Compute what the player sees and mark it as explored.
+    fn recompute_fov(&mut self) {
+        let (player_x, player_y) = self.objects[PLAYER].pos();
+        self.fov.compute_fov(player_x, player_y, TORCH_RADIUS, FOV_LIGHT_WALLS);
+        let (width, height) = map_size(&self.game.map);
+        for y in 0 .. height {
+            for x in 0 .. width {
+                if self.fov.is_in_fov(x, y) {
+                    self.game.map[x as usize][y as usize].explored = true;
+                }
+            }
+        }
+    }
+}
+
+/// Move an object, or open the closed door in its way. Player and monsters
+/// alike open doors by walking into them, and spring the traps they step
+/// on. Returns the kind of trap sprung, if any.
+///
+/// Monsters never jump into a chasm, and get through slow terrain only some
+/// of the time; the player takes longer instead.
+fn move_by(id: usize, dx: i32, dy: i32, objects: &mut [Object], game: &mut Game,
+        fov_map: &FovMap) -> Option<TrapKind> {
+    let (x, y) = (objects[id].x + dx, objects[id].y + dy);
+    let tile = &mut game.map[x as usize][y as usize];
+    let cost = tile.terrain.movement_cost();
+    if tile.door == Some(Door::Closed) {
+        tile.set_door(Door::Open);
+        None
+    } else if id != PLAYER && tile.terrain == Terrain::Chasm {
+        None
+    } else if !is_blocked(x, y, &game.map, objects) {
+        if id != PLAYER && cost > 1 && game.rng.gen_range(0, cost) != 0 {
+            return None;
+        }
+        objects[id].set_pos(x, y);
+        spring_trap(id, objects, game, fov_map)
+    } else {
+        None
+    }
+}
+
+/// Burn everything standing in lava, player and monsters alike.
+fn burn(objects: &mut [Object], game: &mut Game, fov_map: &FovMap) {
+    for (id, object) in objects.iter_mut().enumerate() {
+        let (x, y) = object.pos();
+        let terrain = game.map[x as usize][y as usize].terrain;
+        let damage = terrain.damage_per_turn();
+        if damage <= 0 || !object.alive || object.fighter.is_none() {
+            continue;
+        }
+        if id == PLAYER || fov_map.is_in_fov(x, y) {
+            game.log.add(format!("The {} burns the {} for {} hit points.", terrain.name(), object.name, damage),
+                colors::RED);
+        }
+        object.take_damage_from_dungeon(damage, game);
+    }
+}
+
+/// Spring the trap an object stands on, if there is one. Traps sprung where
+/// the player can see become known.
+fn spring_trap(id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) -> Option<TrapKind> {
+    let (x, y) = objects[id].pos();
+    let kind = match game.map[x as usize][y as usize].trap {
+        Some(trap) => trap.kind,
+        None => return None,
+    };
+    let seen = id == PLAYER || fov_map.is_in_fov(x, y);
+    if seen {
+        game.map[x as usize][y as usize].trap = Some(Trap { kind: kind, known: true });
+        let message = if id == PLAYER {
+            format!("You spring a {}!", kind.name())
+        } else {
+            format!("The {} springs a {}!", objects[id].name, kind.name())
+        };
+        game.log.add(message, colors::ORANGE);
+    }
+
+    match kind {
+        TrapKind::Dart => {
+            if seen {
+                game.log.add(format!("A dart hits the {} for {} hit points.", objects[id].name, DART_DAMAGE),
+                    colors::RED);
+            }
+            objects[id].take_damage_from_dungeon(DART_DAMAGE, game);
+        }
+        TrapKind::Teleport => {
+            if let Some((x, y)) = random_free_spot(objects, game) {
+                objects[id].set_pos(x, y);
+            }
+        }
+        TrapKind::Alarm => {
+            game.log.add("An alarm rings out!", colors::ORANGE);
+            // it wakes those asleep; those confused, running, or already after
+            // the player don't pay attention
+            for monster in objects.iter_mut() {
+                let heard = match monster.ai {
+                    Some(Ai::Confused { .. }) | Some(Ai::Hunting { .. }) | Some(Ai::Fleeing { .. }) | None => {
+                        false
+                    }
+                    Some(_) => monster.distance(x, y) <= ALARM_RADIUS as f32,
+                };
+                if heard {
+                    monster.ai = Some(Ai::Alerted { x: x, y: y, num_turns: ALARM_NUM_TURNS });
+                }
+            }
+        }
+        TrapKind::Pit => {
+            // the player drops to the level below; monsters climb back out
+            if seen {
+                game.log.add(format!("The fall hurts the {} for {} hit points.", objects[id].name, PIT_DAMAGE),
+                    colors::RED);
+            }
+            objects[id].take_damage_from_dungeon(PIT_DAMAGE, game);
+        }
+    }
+    Some(kind)
+}
+
+/// A random place on the level to put something, not blocked, without a
+/// trap and out of lava and chasms. `None` if none was found.
+fn random_free_spot(objects: &[Object], game: &mut Game) -> Option<(i32, i32)> {
+    let (width, height) = map_size(&game.map);
+    for _ in 0 .. FREE_SPOT_TRIES {
+        let (x, y) = (game.rng.gen_range(0, width), game.rng.gen_range(0, height));
+        let tile = &game.map[x as usize][y as usize];
+        if !is_blocked(x, y, &game.map, objects) && tile.trap.is_none() && !tile.terrain.is_hazard() {
+            return Some((x, y));
+        }
+    }
+    None
+}
+
+/// The free place nearest to another, for arriving where someone may
+/// already stand: not blocked, without a trap and out of lava and chasms.
+fn nearest_free_spot(pos: (i32, i32), objects: &[Object], game: &Game) -> Option<(i32, i32)> {
+    flood_fill(&game.map, pos).into_iter().find(|&(x, y)| {
+        let tile = &game.map[x as usize][y as usize];
+        !is_blocked(x, y, &game.map, objects) && tile.trap.is_none() && !tile.terrain.is_hazard()
+    })
+}
+
+/// Look for hidden traps and secret doors around the player; each can be
+/// missed.
+fn search(objects: &[Object], game: &mut Game) {
+    let found = look_around(SEARCH_RADIUS, SEARCH_CHANCE, "You find", objects, game);
+    if !found {
+        game.log.add("You search, but find nothing.", colors::WHITE);
+    }
+}
+
+/// Notice, in passing, something hidden right next to the player. The more
+/// agile the player, the likelier that is.
+fn notice(objects: &[Object], game: &mut Game) {
+    let agility = objects[PLAYER].fighter.map_or(0, |f| cmp::max(0, f.base_defense)) as u32;
+    look_around(1, NOTICE_CHANCE_PER_AGILITY * agility, "You notice", objects, game);
+}
+
+/// Find each hidden thing within `radius` of the player with a `chance` in
+/// percent. Returns whether anything was found.
+fn look_around(radius: i32, chance: u32, verb: &str, objects: &[Object], game: &mut Game) -> bool {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let (width, height) = map_size(&game.map);
+    let mut found = false;
+    for x in cmp::max(0, player_x - radius) .. cmp::min(width, player_x + radius + 1) {
+        for y in cmp::max(0, player_y - radius) .. cmp::min(height, player_y + radius + 1) {
+            let name = match hidden_at(x, y, game) {
+                Some(name) => name,
+                None => continue,
+            };
+            if game.rng.gen_range(0, 100) < chance {
+                reveal(x, y, game);
+                game.log.add(format!("{} a {}.", verb, name), colors::LIGHT_CYAN);
+                found = true;
+            }
+        }
+    }
+    found
+}
+
+/// What is hidden at a place: a trap the player doesn't know about or a
+/// secret door.
+fn hidden_at(x: i32, y: i32, game: &Game) -> Option<&'static str> {
+    let tile = &game.map[x as usize][y as usize];
+    match (tile.door, tile.trap) {
+        (Some(Door::Secret), _) => Some("secret door"),
+        (_, Some(trap)) if !trap.known => Some(trap.kind.name()),
+        _ => None,
+    }
+}
+
+/// Make whatever is hidden at a place known.
+fn reveal(x: i32, y: i32, game: &mut Game) {
+    let tile = &mut game.map[x as usize][y as usize];
+    if tile.door == Some(Door::Secret) {
+        tile.set_door(Door::Closed);
+    } else if let Some(trap) = tile.trap {
+        tile.trap = Some(Trap { kind: trap.kind, known: true });
+    }
+}
+
+fn player_move_or_attack(dx: i32, dy: i32, objects: &mut [Object], game: &mut Game,
+        fov_map: &FovMap) -> Option<TrapKind> {
+    let x = objects[PLAYER].x + dx;
+    let y = objects[PLAYER].y + dy;
+
+    let tile = match game.map.get(x as usize).and_then(|column| column.get(y as usize)) {
+        Some(&tile) => tile,
+        None => return None,
+    };
+    let target_id = objects.iter().position(|object| {
+        object.fighter.is_some() && object.pos() == (x, y)
+    });
+
+    match target_id {
+        Some(target_id) => {
+            let (player, target) = mut_two(PLAYER, target_id, objects);
+            player.attack(target, game);
+            None
+        },
+        None if tile.door == Some(Door::Locked) => {
+            unlock_door(x, y, game);
+            None
+        }
+        None if tile.terrain == Terrain::Chasm
+                && game.dungeon_level >= game.final_depth => {
+            game.log.add("The chasm is too deep to climb down.", colors::WHITE);
+            None
+        }
+        None => move_by(PLAYER, dx, dy, objects, game, fov_map),
+    }
+}
+
+fn unlock_door(x: i32, y: i32, game: &mut Game) {
+    match game.inventory.iter().position(|item| item.item == Some(Item::Key)) {
+        Some(key) => {
+            game.inventory.remove(key);
+            game.map[x as usize][y as usize].set_door(Door::Open);
+            game.log.add("You unlock the door with your key.", colors::LIGHT_CYAN);
+        }
+        None => game.log.add("The door is locked.", colors::WHITE),
+    }
+}
+
+/// Close the door at a place, unless something stands in the doorway.
+/// Returns whether the door was closed.
+fn close_door(x: i32, y: i32, objects: &[Object], game: &mut Game) -> bool {
+    let open = game.map.get(x as usize).and_then(|column| column.get(y as usize))
+        .map_or(false, |tile| tile.door == Some(Door::Open));
+    if !open {
+        game.log.add("There is no open door there.", colors::WHITE);
+        false
+    } else if let Some(object) = objects.iter().find(|object| object.pos() == (x, y)) {
+        game.log.add(format!("The {} is in the way.", object.name), colors::WHITE);
+        false
+    } else {
+        game.map[x as usize][y as usize].set_door(Door::Closed);
+        true
+    }
+}
+
+/// Take a step along the quickest way to a place, going around walls, hazards
+/// and whoever is in the way. With no way there, just head for it.
+fn move_towards(id: usize, target_x: i32, target_y: i32, objects: &mut [Object], game: &mut Game,
+        fov_map: &FovMap) {
+    let (x, y) = objects[id].pos();
+    match find_path(&game.map, objects, (x, y), (target_x, target_y)) {
+        // if someone is on the next tile after all, wait for them to move
+        Some(path) => {
+            let (next_x, next_y) = path[0];
+            move_by(id, next_x - x, next_y - y, objects, game, fov_map);
+        }
+        None => head_towards(id, target_x, target_y, objects, game, fov_map),
+    }
+}
+
+fn head_towards(id: usize, target_x: i32, target_y: i32, objects: &mut [Object], game: &mut Game,
+        fov_map: &FovMap) {
+    let dx = target_x - objects[id].x;
+    let dy = target_y - objects[id].y;
+    let distance = ((dx.pow(2) + dy.pow(2)) as f32).sqrt();
+
+    let dx = (dx as f32 / distance).round() as i32;
+    let dy = (dy as f32 / distance).round() as i32;
+
+    // step around lava, chasms and whatever is in the way, turning at most
+    // one eighth away from the target; stay put if that doesn't work
+    let (x, y) = objects[id].pos();
+    let can_enter = |(dx, dy): (i32, i32)| {
+        let (x, y) = (x + dx, y + dy);
+        let tile = &game.map[x as usize][y as usize];
+        !tile.terrain.is_hazard() && (tile.door == Some(Door::Closed) || !is_blocked(x, y, &game.map, objects))
+    };
+    let step = match DIRECTIONS.iter().position(|&direction| direction == (dx, dy)) {
+        Some(i) => [0, 1, DIRECTIONS.len() - 1].iter()
+            .map(|turn| DIRECTIONS[(i + turn) % DIRECTIONS.len()])
+            .find(|&direction| can_enter(direction)),
+        None => Some((dx, dy)),
+    };
+    if let Some((dx, dy)) = step {
+        move_by(id, dx, dy, objects, game, fov_map);
+    }
+}
+
+fn ai_take_turn(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game) {
+    use Ai::*;
+    if let Some(ranged) = objects[monster_id].ranged.as_mut() {
+        ranged.recharging = cmp::max(0, ranged.recharging - 1);
+    }
+    if let Some(ai) = objects[monster_id].ai.take() {
+        let ai = lose_heart(monster_id, objects, fov_map, game, ai);
+        let new_ai = match ai {
+            Basic => ai_basic(monster_id, objects, fov_map, game),
+            Asleep => ai_asleep(monster_id, objects, fov_map, game),
+            Wandering { x, y } => ai_wandering(monster_id, objects, fov_map, game, (x, y)),
+            Hunting { x, y } => ai_hunting(monster_id, objects, fov_map, game, (x, y)),
+            Searching { x, y, num_turns } => ai_searching(monster_id, objects, fov_map, game, (x, y), num_turns),
+            Confused { previous_ai, num_turns } => ai_confused(
+                monster_id, objects, fov_map, game, previous_ai, num_turns),
+            Fleeing { num_turns } => ai_fleeing(monster_id, objects, fov_map, game, num_turns),
+            Alerted { x, y, num_turns } => ai_alerted(monster_id, objects, fov_map, game, (x, y), num_turns),
+        };
+        objects[monster_id].ai = Some(new_ai);
+    }
+}
+
+/// Have a monster run if it's badly hurt, or if the leader of its pack is
+/// dead. Asleep or confused, it's in no state to notice.
+fn lose_heart(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game, ai: Ai) -> Ai {
+    let morale = match (objects[monster_id].morale, &ai) {
+        (_, &Ai::Asleep) | (_, &Ai::Confused { .. }) | (_, &Ai::Fleeing { .. }) | (None, _) => return ai,
+        (Some(morale), _) => morale,
+    };
+    let leader_alive = objects.iter().any(|object| {
+        object.alive && object.morale.map_or(false, |other| other.leader && other.pack == morale.pack)
+    });
+    let num_turns = if morale.pack.is_some() && !leader_alive {
+        // the pack is no more
+        if let Some(morale) = objects[monster_id].morale.as_mut() {
+            morale.pack = None;
+        }
+        PACK_BREAK_TURNS
+    } else if badly_hurt(&objects[monster_id]) {
+        FLEE_TURNS
+    } else {
+        return ai;
+    };
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if fov_map.is_in_fov(monster_x, monster_y) {
+        game.log.add(format!("The {} flees!", objects[monster_id].name), colors::ORANGE);
+    }
+    Ai::Fleeing { num_turns: num_turns }
+}
+
+fn badly_hurt(monster: &Object) -> bool {
+    match (monster.fighter, monster.morale) {
+        (Some(fighter), Some(morale)) => fighter.hp * 100 < fighter.base_max_hp * morale.flee_below,
+        _ => false,
+    }
+}
+
+/// Whether a monster sees the player: they are in its sight and nothing
+/// stands in between.
+fn sees_player(monster_id: usize, objects: &[Object], game: &Game) -> bool {
+    let monster = &objects[monster_id];
+    let sight_radius = monster.senses.map_or(0, |senses| senses.sight_radius);
+    if monster.distance_to(&objects[PLAYER]) > sight_radius as f32 {
+        return false;
+    }
+    let cells = line(monster.pos(), objects[PLAYER].pos());
+    cells[1 .. cells.len() - 1].iter().all(|&(x, y)| !game.map[x as usize][y as usize].block_sight)
+}
+
+fn hears_player(monster_id: usize, objects: &[Object]) -> bool {
+    let hearing_radius = objects[monster_id].senses.map_or(0, |senses| senses.hearing_radius);
+    objects[monster_id].distance_to(&objects[PLAYER]) <= hearing_radius as f32
+}
+
+/// Whether a monster that isn't after the player yet notices them this
+/// turn. It can't miss hearing them, but may overlook them in sight.
+fn notices_player(monster_id: usize, objects: &[Object], game: &mut Game) -> bool {
+    let perception = objects[monster_id].senses.map_or(0, |senses| senses.perception);
+    hears_player(monster_id, objects)
+        || (sees_player(monster_id, objects, game) && game.rng.gen_range(0, 100) < perception)
+}
+
+/// Whether a monster after the player still knows where they are.
+fn tracks_player(monster_id: usize, objects: &[Object], game: &Game) -> bool {
+    hears_player(monster_id, objects) || sees_player(monster_id, objects, game)
+}
+
+/// Close in on the player and fight them, remembering where they were.
+/// Monsters that shoot keep their distance instead, and wait for a shot once
+/// they have one.
+fn fight(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game) -> Ai {
+    let (player_x, player_y) = objects[PLAYER].pos();
+    let distance = objects[monster_id].distance_to(&objects[PLAYER]);
+    if objects[monster_id].ranged.is_some() {
+        // next to the player and not ready to shoot, it fights as others do
+        let acted = (distance < KEEP_AWAY_DISTANCE as f32 && back_away(monster_id, objects, game, fov_map))
+            || (can_shoot(monster_id, objects, game)
+                && (shoot(monster_id, objects, fov_map, game) || distance >= 2.0));
+        if acted {
+            return Ai::Hunting { x: player_x, y: player_y };
+        }
+    }
+    if distance >= 2.0 {
+        move_towards(monster_id, player_x, player_y, objects, game, fov_map);
+    } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
+        let (monster, player) = mut_two(monster_id, PLAYER, objects);
+        monster.attack(player, game);
+    }
+    Ai::Hunting { x: player_x, y: player_y }
+}
+
+/// Step away from the player, if there's anywhere to go.
+fn back_away(monster_id: usize, objects: &mut [Object], game: &mut Game, fov_map: &FovMap) -> bool {
+    let (x, y) = objects[monster_id].pos();
+    let distance = objects[monster_id].distance_to(&objects[PLAYER]);
+    let away = DIRECTIONS.iter()
+        .map(|&(dx, dy)| (dx, dy, objects[PLAYER].distance(x + dx, y + dy)))
+        .filter(|&(dx, dy, further)| {
+            let (x, y) = (x + dx, y + dy);
+            further > distance && !is_blocked(x, y, &game.map, objects)
+                && !game.map[x as usize][y as usize].terrain.is_hazard()
+        })
+        .fold(None, |best: Option<(i32, i32, f32)>, step| match best {
+            Some(best) if best.2 >= step.2 => Some(best),
+            _ => Some(step),
+        });
+    match away {
+        Some((dx, dy, _)) => {
+            move_by(monster_id, dx, dy, objects, game, fov_map);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Where a shot from one place at another stops: at the target, or at the
+/// first wall, closed door or monster in the way.
+fn line_of_fire(from: (i32, i32), to: (i32, i32), objects: &[Object], game: &Game) -> (i32, i32) {
+    line(from, to).into_iter()
+        .skip(1)
+        .find(|&(x, y)| (x, y) == to || is_blocked(x, y, &game.map, objects))
+        .unwrap_or(to)
+}
+
+/// Whether the player is in range of a monster's shots, with nothing in the
+/// way.
+fn can_shoot(monster_id: usize, objects: &[Object], game: &Game) -> bool {
+    let range = objects[monster_id].ranged.map_or(0, |ranged| ranged.range);
+    objects[monster_id].distance_to(&objects[PLAYER]) <= range as f32
+        && line_of_fire(objects[monster_id].pos(), objects[PLAYER].pos(), objects, game) == objects[PLAYER].pos()
+}
+
+/// Shoot or cast at the player, unless the monster isn't ready yet.
+fn shoot(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game) -> bool {
+    let ranged = match objects[monster_id].ranged {
+        Some(ranged) if ranged.recharging == 0 => ranged,
+        _ => return false,
+    };
+    if let Some(ranged) = objects[monster_id].ranged.as_mut() {
+        ranged.recharging = ranged.recharge;
+    }
+
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    let (verb, projectile) = (ranged.projectile.verb(), ranged.projectile.name());
+    let message = if fov_map.is_in_fov(monster_x, monster_y) {
+        format!("The {} {} {} at you.", objects[monster_id].name, verb, projectile)
+    } else {
+        format!("Something {} {} at you out of the dark.", verb, projectile)
+    };
+    game.log.add(message, colors::ORANGE);
+    let damage = match ranged.projectile {
+        Projectile::Arrow => ranged.power - objects[PLAYER].defense(game),
+        Projectile::Lightning => ranged.power,
+    };
+    if damage > 0 {
+        game.log.add(format!("It hits you for {} hit points.", damage), colors::RED);
+        objects[PLAYER].take_damage(damage, game);
+    } else {
+        game.log.add("It glances off your armor.", colors::RED);
+    }
+    true
+}
+
+fn ai_basic(monster_id: usize, objects: &mut [Object],
+                fov_map: &FovMap, game: &mut Game) -> Ai {
+    if notices_player(monster_id, objects, game) {
+        fight(monster_id, objects, fov_map, game)
+    } else {
+        Ai::Basic
+    }
+}
+
+/// Sleep on, unless hurt, or woken by noticing the player.
+fn ai_asleep(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game) -> Ai {
+    let hurt = objects[monster_id].fighter.map_or(false, |f| f.hp < f.base_max_hp);
+    let perception = objects[monster_id].senses.map_or(0, |senses| senses.perception);
+    let noticed = tracks_player(monster_id, objects, game)
+        && game.rng.gen_range(0, 100) < perception / ASLEEP_PERCEPTION_DIVISOR;
+    if !hurt && !noticed {
+        return Ai::Asleep;
+    }
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if noticed {
+        if fov_map.is_in_fov(monster_x, monster_y) {
+            game.log.add(format!("The {} wakes up!", objects[monster_id].name), colors::ORANGE);
+        }
+        let (player_x, player_y) = objects[PLAYER].pos();
+        Ai::Hunting { x: player_x, y: player_y }
+    } else {
+        Ai::Searching { x: monster_x, y: monster_y, num_turns: MONSTER_SEARCH_TURNS }
+    }
+}
+
+/// Roam from one random place to the next until the player comes into view.
+fn ai_wandering(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                (x, y): (i32, i32)) -> Ai {
+    if notices_player(monster_id, objects, game) {
+        return fight(monster_id, objects, fov_map, game);
+    }
+    let position = objects[monster_id].pos();
+    let (x, y) = if position == (x, y) {
+        random_free_spot(objects, game).unwrap_or(position)
+    } else {
+        (x, y)
+    };
+    move_towards(monster_id, x, y, objects, game, fov_map);
+    // stuck: go somewhere else next time
+    if objects[monster_id].pos() == position {
+        Ai::Wandering { x: position.0, y: position.1 }
+    } else {
+        Ai::Wandering { x: x, y: y }
+    }
+}
+
+/// Follow the player to where they were last seen, then look around there.
+fn ai_hunting(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                (x, y): (i32, i32)) -> Ai {
+    if tracks_player(monster_id, objects, game) {
+        return fight(monster_id, objects, fov_map, game);
+    }
+    let position = objects[monster_id].pos();
+    if position != (x, y) {
+        move_towards(monster_id, x, y, objects, game, fov_map);
+    }
+    if objects[monster_id].pos() == position {
+        Ai::Searching { x: x, y: y, num_turns: MONSTER_SEARCH_TURNS }
+    } else {
+        Ai::Hunting { x: x, y: y }
+    }
+}
+
+/// Wander around a place the player was last heard or seen at for a while,
+/// then give up.
+fn ai_searching(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                (x, y): (i32, i32), num_turns: i32) -> Ai {
+    if notices_player(monster_id, objects, game) {
+        return fight(monster_id, objects, fov_map, game);
+    }
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if num_turns <= 0 {
+        return Ai::Wandering { x: monster_x, y: monster_y };
+    }
+    let dx = game.rng.gen_range(-1, 2);
+    let dy = game.rng.gen_range(-1, 2);
+    let (next_x, next_y) = (monster_x + dx, monster_y + dy);
+    if cmp::max((next_x - x).abs(), (next_y - y).abs()) > MONSTER_SEARCH_RADIUS {
+        move_towards(monster_id, x, y, objects, game, fov_map);
+    } else if !game.map[next_x as usize][next_y as usize].terrain.is_hazard() {
+        move_by(monster_id, dx, dy, objects, game, fov_map);
+    }
+    Ai::Searching { x: x, y: y, num_turns: num_turns - 1 }
+}
+
+/// Run from the player to safety, getting better on the way, and fight
+/// again once healed and over the fright. Cornered, it fights back.
+fn ai_fleeing(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                num_turns: i32) -> Ai {
+    if game.rng.gen_range(0, FLEE_REGEN_TURNS) == 0 {
+        objects[monster_id].heal(1, game);
+    }
+    let healed = match (objects[monster_id].fighter, objects[monster_id].morale) {
+        (Some(fighter), Some(morale)) => fighter.hp * 100 >= fighter.base_max_hp * morale.flee_below * 2,
+        _ => true,
+    };
+    if num_turns <= 0 && healed {
+        return ai_basic(monster_id, objects, fov_map, game);
+    }
+
+    let safety = safety_map(&game.map, objects[PLAYER].pos());
+    let (x, y) = objects[monster_id].pos();
+    let here = safety[x as usize][y as usize].unwrap_or(i32::MAX);
+    let safest = DIRECTIONS.iter()
+        .filter_map(|&(dx, dy)| {
+            let (x, y) = (x + dx, y + dy);
+            let tile = &game.map[x as usize][y as usize];
+            let free = tile.door == Some(Door::Closed) || !is_blocked(x, y, &game.map, objects);
+            match safety[x as usize][y as usize] {
+                Some(value) if value < here && free => Some((value, (dx, dy))),
+                _ => None,
+            }
+        })
+        .min();
+    match safest {
+        Some((_, (dx, dy))) => {
+            move_by(monster_id, dx, dy, objects, game, fov_map);
+        }
+        None if objects[monster_id].distance_to(&objects[PLAYER]) < 2.0 => {
+            // it fights back, and runs again as soon as it can
+            if fov_map.is_in_fov(x, y) {
+                game.log.add(format!("The {} is cornered!", objects[monster_id].name), colors::ORANGE);
+            }
+            fight(monster_id, objects, fov_map, game);
+        }
+        None => {}
+    }
+    Ai::Fleeing { num_turns: num_turns - 1 }
+}
+
+/// Head for a noise until the player comes into view, then fight as usual.
+fn ai_alerted(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                (x, y): (i32, i32), num_turns: i32) -> Ai {
+    let (monster_x, monster_y) = objects[monster_id].pos();
+    if notices_player(monster_id, objects, game) {
+        fight(monster_id, objects, fov_map, game)
+    } else if num_turns <= 0 || (monster_x, monster_y) == (x, y) {
+        Ai::Searching { x: x, y: y, num_turns: MONSTER_SEARCH_TURNS }
+    } else {
+        move_towards(monster_id, x, y, objects, game, fov_map);
+        Ai::Alerted { x: x, y: y, num_turns: num_turns - 1 }
+    }
+}
+
+fn ai_confused(monster_id: usize, objects: &mut [Object], fov_map: &FovMap, game: &mut Game,
+                previous_ai: Box<Ai>, num_turns: i32) -> Ai {
+    if num_turns >= 0 {
+        let dx = game.rng.gen_range(-1, 2);
+        let dy = game.rng.gen_range(-1, 2);
+        move_by(monster_id, dx, dy, objects, game, fov_map);
+        Ai::Confused { previous_ai: previous_ai, num_turns: num_turns - 1 }
+    } else {
+        game.log.add(format!("The {} is no longer confused!",
+            objects[monster_id].name),
+        colors::RED);
+        *previous_ai
+    }
+}
+
+fn pick_item_up(object_id: usize, objects: &mut Vec<Object>, game: &mut Game) {
+    if game.inventory.len() >= 26 {
+        game.log.add(format!("Your inventory is full, cannot pick up {}.", objects[object_id].name),
+            colors::RED);
+    } else {
+        let item = objects.swap_remove(object_id);
+        game.log.add(format!("You picked up a {}!", item.name),
+            colors::GREEN);
+        let index = game.inventory.len();
+        let slot = item.equipment.map(|e| e.slot);
+        game.inventory.push(item);
+
+        if let Some(slot) = slot {
+            if get_equipped_in_slot(slot, &game.inventory).is_none() {
+                game.inventory[index].equip(&mut game.log);
+            }
+        }
+    }
+}
+
+fn drop_item(inventory_id: usize, objects: &mut Vec<Object>,
+        game: &mut Game) {
+    let mut item = game.inventory.remove(inventory_id);
+    if item.equipment.is_some() {
+        item.dequip(&mut game.log);
+    }
+    item.set_pos(objects[PLAYER].x, objects[PLAYER].y);
+    game.log.add(format!("You dropped a {}.", item.name), colors::YELLOW);
+    objects.push(item);
+}
+
+fn use_item(inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object],
+        game: &mut Game, fov_map: &FovMap) {
+    use Item::*;
+
+    if let Some(item) = game.inventory[inventory_id].item {
+        let on_use = match item {
+            Heal => cast_heal,
+            Lightning => cast_lightning,
+            Confuse => cast_confuse,
+            Fireball => cast_fireball,
+            Sword => toggle_equipment,
+            Shield => toggle_equipment,
+            Artifact => contemplate_artifact,
+            Key => inspect_key,
+        };
+
+        match on_use(inventory_id, target, objects, game, fov_map) {
+            UseResult::UsedUp => {
+                game.inventory.remove(inventory_id);
+            },
+            UseResult::UsedAndKept => {},
+            UseResult::Cancelled => {
+                game.log.add("Cancelled", colors::WHITE);
+            },
+        }
+    } else {
+        game.log.add(format!("The {} cannot be used.", game.inventory[inventory_id].name),
+            colors::WHITE);
+    }
+}
+
+fn contemplate_artifact(_inventory_id: usize, _target: Option<(i32, i32)>, _objects: &mut [Object],
+    game: &mut Game, _fov_map: &FovMap) -> UseResult {
+    game.log.add("The amulet hums in your hands. It wants to see the sun again.", colors::YELLOW);
+    UseResult::UsedAndKept
+}
+
+fn inspect_key(_inventory_id: usize, _target: Option<(i32, i32)>, _objects: &mut [Object],
+    game: &mut Game, _fov_map: &FovMap) -> UseResult {
+    game.log.add("Walk into a locked door to open it with the key.", colors::WHITE);
+    UseResult::UsedAndKept
+}
+
+fn toggle_equipment(inventory_id: usize, _target: Option<(i32, i32)>, _objects: &mut [Object],
+    game: &mut Game, _fov_map: &FovMap) -> UseResult {
+    let equipment = match game.inventory[inventory_id].equipment {
+        Some(equipment) => equipment,
+        None => return UseResult::Cancelled,
+    };
+    if let Some(old_equipment) = get_equipped_in_slot(equipment.slot, &game.inventory) {
+        game.inventory[old_equipment].dequip(&mut game.log);
+    }
+    if equipment.equipped {
+        game.inventory[inventory_id].dequip(&mut game.log);
+    } else {
+        game.inventory[inventory_id].equip(&mut game.log);
+    }
+    UseResult::UsedAndKept
+}
+
+fn cast_heal(_inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object],
+        game: &mut Game, _fov_map: &FovMap) -> UseResult {
+    if let Some(fighter) = objects[PLAYER].fighter {
+        if fighter.hp == objects[PLAYER].max_hp(game) {
+            game.log.add("You are already at full health.", colors::RED);
+            return UseResult::Cancelled;
+        }
+
+        game.log.add("Your wounds start to feel better!", colors::LIGHT_VIOLET);
+        objects[PLAYER].heal(HEAL_AMOUNT, game);
+        return UseResult::UsedUp;
+    }
+    UseResult::Cancelled
+}
+
+fn cast_lightning(_inventory_id: usize, _target: Option<(i32, i32)>, objects: &mut [Object],
+        game: &mut Game, fov_map: &FovMap) -> UseResult {
+    let monster_id = closest_monster(LIGHTNING_RANGE, objects, fov_map);
+    if let Some(monster_id) = monster_id {
+        game.log.add(
+            format!("A lightning bolt strikes the {} with a loud thunder! The damage is {} hit points.",
+                objects[monster_id].name, LIGHTNING_DAMAGE),
+            colors::LIGHT_BLUE);
+        if let Some(xp) = objects[monster_id].take_damage(LIGHTNING_DAMAGE, game) {
+            objects[PLAYER].fighter.as_mut().unwrap().xp += xp;
+        }
+        UseResult::UsedUp
+    } else {
+        game.log.add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+fn cast_confuse(_inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object],
+        game: &mut Game, fov_map: &FovMap) -> UseResult {
+    let monster_id = target.and_then(|(x, y)| {
+        let in_range = objects[PLAYER].distance(x, y) <= CONFUSE_RANGE as f32;
+        objects.iter().enumerate().position(|(id, obj)| {
+            in_range && fov_map.is_in_fov(x, y) &&
+                obj.pos() == (x, y) && obj.fighter.is_some() && id != PLAYER
+        })
+    });
+    if let Some(monster_id) = monster_id {
+        let old_ai = objects[monster_id].ai.take().unwrap_or(Ai::Basic);
+
+        objects[monster_id].ai = Some(Ai::Confused {
+            previous_ai: Box::new(old_ai),
+            num_turns: CONFUSE_NUM_TURNS,
+        });
+        game.log.add(
+            format!("The eyes of {} look vacant, as he starts to stumble around!",
+                objects[monster_id].name),
+            colors::LIGHT_GREEN);
+        UseResult::UsedUp
+    } else {
+        game.log.add("No enemy is close enough to strike.", colors::RED);
+        UseResult::Cancelled
+    }
+}
+
+fn cast_fireball(_inventory_id: usize, target: Option<(i32, i32)>, objects: &mut [Object],
+        game: &mut Game, fov_map: &FovMap) -> UseResult {
+    // only the field of view is on the map, so that's enough to check
+    let (x, y) = match target {
+        Some((x, y)) if fov_map.is_in_fov(x, y) => (x, y),
+        _ => return UseResult::Cancelled,
+    };
+
+    game.log.add(format!("The fireball explodes, burning everything within {} tiles!",
+        FIREBALL_RADIUS), colors::ORANGE);
+
+    let mut xp_to_gain = 0;
+
+    for (id, obj) in objects.iter_mut().enumerate() {
+        if obj.distance(x, y) <= FIREBALL_RADIUS as f32 && obj.fighter.is_some() {
+            game.log.add(
+                format!("The {} gets burned for {} hit poitns.", obj.name, FIREBALL_DAMAGE),
+                colors::ORANGE);
+            if let Some(xp) = obj.take_damage(FIREBALL_DAMAGE, game) {
+                if id != PLAYER {
+                    xp_to_gain += xp;
+                }
+            }
+        }
+    }
+    objects[PLAYER].fighter.as_mut().unwrap().xp += xp_to_gain;
+
+    UseResult::UsedUp
+}
+
+fn closest_monster(max_range: i32, objects: &mut [Object], fov_map: &FovMap) -> Option<usize> {
+    let mut closest_enemy = None;
+    let mut closest_dist = (max_range + 1) as f32;
+
+    for (id, object) in objects.iter().enumerate() {
+        if (id != PLAYER) && object.fighter.is_some() && object.ai.is_some() &&
+            fov_map.is_in_fov(object.x, object.y) {
+                let dist = objects[PLAYER].distance_to(object);
+                if dist < closest_dist {
+                    closest_enemy = Some(id);
+                    closest_dist = dist;
+                }
+            }
+    }
+    closest_enemy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map::{drawn_map, make_item, make_monster};
+    use object::Morale;
+    use save::encode_save;
+
+    const SCRIPT: &'static [&'static str] = &[
+        "move 1 0", "move 1 1", "move 0 1", "wait", "search", "move -1 1", "move -1 0",
+        "pickup", "move -1 -1", "move 0 -1", "use 0", "move 1 -1", "wait", "drop 0",
+        "close 1 0", "stairs", "move 1 0", "move 1 0", "move 0 1", "move 0 1", "wait",
+    ];
+
+    #[test]
+    fn same_seed_and_commands_give_the_same_game() {
+        for seed in 0..20 {
+            let mut first = World::new(seed, GameMode::Exploration);
+            let mut second = World::new(seed, GameMode::Exploration);
+            for _ in 0..5 {
+                for line in SCRIPT {
+                    let command: Command = line.parse().unwrap();
+                    let (a, b) = (first.step(command), second.step(command));
+                    assert_eq!(a.took_turn, b.took_turn, "seed {}, '{}'", seed, line);
+                    assert_eq!(a.messages, b.messages, "seed {}, '{}'", seed, line);
+                    assert_eq!(first.player().pos(), second.player().pos(), "seed {}, '{}'", seed, line);
+                }
+            }
+            for &format in &[SaveFormat::Json, SaveFormat::Binary, SaveFormat::Compressed] {
+                first.save_format = format;
+                second.save_format = format;
+                assert!(encode_save(&first, 0).unwrap() == encode_save(&second, 0).unwrap(),
+                        "seed {}, {:?}", seed, format);
+            }
+        }
+    }
+
+    #[test]
+    fn commands_parse() {
+        assert_eq!("move -1 1".parse(), Ok(Command::Move(-1, 1)));
+        assert_eq!("  wait ".parse(), Ok(Command::Wait));
+        assert_eq!("pickup".parse(), Ok(Command::PickUp));
+        assert_eq!("use 2".parse(), Ok(Command::UseItem { inventory_id: 2, target: None }));
+        assert_eq!("use 0 10 -3".parse(), Ok(Command::UseItem { inventory_id: 0, target: Some((10, -3)) }));
+        assert_eq!("drop 4".parse(), Ok(Command::DropItem(4)));
+        assert_eq!("close 0 -1".parse(), Ok(Command::CloseDoor(0, -1)));
+        assert_eq!("search".parse(), Ok(Command::Search));
+        assert_eq!("stairs".parse(), Ok(Command::TakeStairs));
+        assert_eq!("levelup con".parse(), Ok(Command::LevelUp(Stat::Constitution)));
+        assert_eq!("levelup str".parse(), Ok(Command::LevelUp(Stat::Strength)));
+        assert_eq!("levelup agi".parse(), Ok(Command::LevelUp(Stat::Agility)));
+        assert_eq!("wizard reveal".parse(), Ok(Command::Wizard(WizardCommand::RevealMap)));
+        assert_eq!("wizard descend".parse(), Ok(Command::Wizard(WizardCommand::Descend)));
+        assert_eq!("wizard heal".parse(), Ok(Command::Wizard(WizardCommand::Heal)));
+    }
+
+    #[test]
+    fn bad_commands_are_rejected() {
+        let error = |line: &str| line.parse::<Command>().unwrap_err();
+        assert_eq!(error(""), "Empty command.");
+        assert_eq!(error("   "), "Empty command.");
+        assert_eq!(error("jump"), "Unknown command 'jump'.");
+        assert_eq!(error("move 1"), "Missing argument.");
+        assert_eq!(error("move x 1"), "'x' is not a number.");
+        assert_eq!(error("move 5 0"), "Steps go from -1 to 1, not 5.");
+        assert_eq!(error("move -100 0"), "Steps go from -1 to 1, not -100.");
+        assert_eq!(error("move 0 2"), "Steps go from -1 to 1, not 2.");
+        assert_eq!(error("close -2 0"), "Steps go from -1 to 1, not -2.");
+        assert_eq!(error("use 1 2"), "Missing argument.");
+        assert_eq!(error("drop -1"), "'-1' is not a number.");
+        assert_eq!(error("wait 3"), "Unexpected '3' after the command.");
+        assert_eq!(error("levelup"), "Level up needs one of con, str or agi.");
+        assert_eq!(error("levelup luck"), "Level up needs one of con, str or agi.");
+        assert_eq!(error("wizard fly"), "Wizard needs one of reveal, descend or heal.");
+    }
+
+    /// A world on a hand-drawn map, with nobody on it but the player.
+    fn world_on(rows: &[&str], (x, y): (i32, i32)) -> World {
+        let mut world = World::new(1, GameMode::Exploration);
+        world.objects.truncate(1);
+        world.objects[PLAYER].set_pos(x, y);
+        world.game.map = drawn_map(rows);
+        World::from_parts(world.objects, world.game)
+    }
+
+    /// An orc that stays where it's put.
+    fn still_orc(x: i32, y: i32) -> Object {
+        let mut orc = make_monster("orc", x, y);
+        orc.ai = None;
+        orc
+    }
+
+    #[test]
+    fn moves_go_a_step_at_most_and_stay_on_the_map() {
+        let mut world = world_on(&["......"], (0, 0));
+        world.objects.push(still_orc(3, 0));
+        for &(dx, dy) in &[(-1, 0), (0, -1), (0, 1), (-1, 1), (-2, 0), (3, 0), (100, -100)] {
+            world.step(Command::Move(dx, dy));
+            assert_eq!(world.player().pos(), (0, 0), "{:?}", (dx, dy));
+        }
+        assert!(!world.step(Command::Move(3, 0)).took_turn);
+        assert!(!world.step(Command::CloseDoor(0, 5)).took_turn);
+        let orc = world.objects[1].fighter.unwrap();
+        assert_eq!(orc.hp, orc.base_max_hp);
+    }
+
+    #[test]
+    fn fireballs_need_a_target_in_view() {
+        let mut world = world_on(&[
+            "#######",
+            "#..#..#",
+            "#######",
+        ], (1, 1));
+        world.game.inventory = vec![make_item(Item::Fireball, 0, 0)];
+        world.objects.push(still_orc(5, 1));
+        for &target in &[(5, 1), (-3, 1), (1, 40)] {
+            world.step(Command::UseItem { inventory_id: 0, target: Some(target) });
+            assert_eq!(world.game.inventory.len(), 1, "{:?}", target);
+        }
+        let orc = world.objects[1].fighter.unwrap();
+        assert_eq!(orc.hp, orc.base_max_hp);
+
+        world.step(Command::UseItem { inventory_id: 0, target: Some((2, 1)) });
+        assert!(world.game.inventory.is_empty());
+    }
+
+    #[test]
+    fn arriving_on_taken_stairs_puts_the_player_next_to_them() {
+        for seed in 0..10 {
+            let mut world = World::new(seed, GameMode::Exploration);
+            world.game.wizard = true;
+            world.step(Command::Wizard(WizardCommand::Descend));
+            let level = world.game.levels.get_mut(&1).unwrap();
+            let stairs = level.objects.iter().find(|object| object.name == "down stairs").unwrap().pos();
+            level.objects.push(still_orc(stairs.0, stairs.1));
+
+            world.step(Command::TakeStairs);
+            assert_eq!(world.game.dungeon_level, 1);
+            let (x, y) = world.player().pos();
+            assert!((x, y) != stairs && world.player().distance(stairs.0, stairs.1) < 3.0, "seed {}", seed);
+            assert_eq!(world.objects.iter().filter(|object| object.blocks && object.pos() == (x, y)).count(), 1);
+        }
+    }
+
+    /// A world with a goblin archer at the west end of a hall, and the
+    /// archer's index.
+    fn world_with_archer(hall: &str, player: (i32, i32)) -> (World, usize) {
+        let wall = "#".repeat(hall.len());
+        let mut world = world_on(&[&wall, hall, &wall], player);
+        world.objects.push(make_monster("goblin archer", 1, 1));
+        (world, 1)
+    }
+
+    #[test]
+    fn archers_shoot_when_the_way_is_clear() {
+        let (mut world, archer) = world_with_archer("#.........#", (5, 1));
+        assert_eq!(line_of_fire((1, 1), (5, 1), &world.objects, &world.game), (5, 1));
+        assert!(can_shoot(archer, &world.objects, &world.game));
+
+        world.objects[archer].ai = Some(Ai::Hunting { x: 5, y: 1 });
+        let hp = world.player().fighter.unwrap().hp;
+        world.step(Command::Wait);
+        assert!(world.player().fighter.unwrap().hp < hp);
+        assert_eq!(world.objects[archer].pos(), (1, 1));
+    }
+
+    #[test]
+    fn walls_stop_shots() {
+        let (world, archer) = world_with_archer("#..#.....#", (5, 1));
+        assert_eq!(line_of_fire((1, 1), (5, 1), &world.objects, &world.game), (3, 1));
+        assert!(!can_shoot(archer, &world.objects, &world.game));
+    }
+
+    #[test]
+    fn monsters_stop_shots() {
+        let (mut world, archer) = world_with_archer("#.........#", (5, 1));
+        world.objects.push(still_orc(3, 1));
+        assert_eq!(line_of_fire((1, 1), (5, 1), &world.objects, &world.game), (3, 1));
+        assert!(!can_shoot(archer, &world.objects, &world.game));
+    }
+
+    #[test]
+    fn shots_only_go_so_far() {
+        let (world, archer) = world_with_archer("#...........#", (10, 1));
+        assert_eq!(line_of_fire((1, 1), (10, 1), &world.objects, &world.game), (10, 1));
+        assert!(world.objects[archer].distance_to(world.player()) > world.objects[archer].ranged.unwrap().range as f32);
+        assert!(!can_shoot(archer, &world.objects, &world.game));
+    }
+
+    const ROOM: &'static [&'static str] = &[
+        "####################",
+        "#..................#",
+        "#..................#",
+        "#..................#",
+        "#..................#",
+        "#..................#",
+        "####################",
+    ];
+
+    fn is_fleeing(monster: &Object) -> bool {
+        matches!(monster.ai, Some(Ai::Fleeing { .. }))
+    }
+
+    #[test]
+    fn badly_hurt_monsters_flee() {
+        let mut world = world_on(ROOM, (2, 3));
+        world.objects.push(make_monster("orc", 3, 3));
+        world.objects[1].ai = Some(Ai::Hunting { x: 2, y: 3 });
+        world.objects[1].fighter.as_mut().unwrap().hp = 3;
+
+        let turn = world.step(Command::Wait);
+        assert!(is_fleeing(&world.objects[1]));
+        assert!(turn.messages.iter().any(|&(ref message, _)| message == "The orc flees!"));
+        assert!(world.objects[1].distance_to(world.player()) >= 2.0);
+        assert_eq!(world.player().fighter.unwrap().hp, 100);
+    }
+
+    #[test]
+    fn fleeing_monsters_rally_once_healed() {
+        let mut world = world_on(ROOM, (2, 3));
+        world.objects.push(make_monster("orc", 12, 3));
+        world.objects[1].ai = Some(Ai::Fleeing { num_turns: 1 });
+        world.step(Command::Wait);
+        assert!(is_fleeing(&world.objects[1]));
+        world.step(Command::Wait);
+        assert!(!is_fleeing(&world.objects[1]));
+    }
+
+    #[test]
+    fn cornered_monsters_fight_back() {
+        let mut world = world_on(&["######", "#....#", "######"], (2, 1));
+        world.objects.push(make_monster("orc", 1, 1));
+        world.objects[1].ai = Some(Ai::Fleeing { num_turns: FLEE_TURNS });
+        world.objects[1].fighter.as_mut().unwrap().hp = 3;
+
+        let turn = world.step(Command::Wait);
+        assert!(turn.messages.iter().any(|&(ref message, _)| message == "The orc is cornered!"));
+        assert!(world.player().fighter.unwrap().hp < 100);
+        assert!(is_fleeing(&world.objects[1]));
+    }
+
+    #[test]
+    fn packs_break_when_their_leader_dies() {
+        let mut world = world_on(ROOM, (2, 3));
+        for &(x, leader) in &[(15, true), (16, false), (17, false)] {
+            let mut orc = make_monster("orc", x, 3);
+            orc.morale = Some(Morale { flee_below: 20, pack: Some((15, 3)), leader: leader });
+            world.objects.push(orc);
+        }
+        world.step(Command::Wait);
+        assert!(world.objects[2 ..].iter().all(|orc| !is_fleeing(orc)));
+
+        world.objects[1].take_damage(100, &mut world.game);
+        world.step(Command::Wait);
+        for orc in &world.objects[2 ..] {
+            assert!(is_fleeing(orc));
+            assert_eq!(orc.morale.unwrap().pack, None);
+        }
+    }
+
+    /// A world with the player at the west end of `ROOM` and an orc doing
+    /// something else at the east end, well out of its sight and hearing.
+    fn world_with_orc(ai: Ai) -> World {
+        let mut world = world_on(ROOM, (2, 3));
+        world.objects.push(make_monster("orc", 17, 3));
+        world.objects[1].ai = Some(ai);
+        world
+    }
+
+    #[test]
+    fn sleepers_sleep_on_until_they_notice_the_player() {
+        let mut world = world_with_orc(Ai::Asleep);
+        for _ in 0..50 {
+            world.step(Command::Wait);
+        }
+        assert!(matches!(world.objects[1].ai, Some(Ai::Asleep)));
+
+        // within earshot, it wakes up sooner or later
+        world.objects[1].set_pos(4, 3);
+        for _ in 0..100 {
+            world.step(Command::Wait);
+            if !matches!(world.objects[1].ai, Some(Ai::Asleep)) {
+                break;
+            }
+        }
+        assert!(matches!(world.objects[1].ai, Some(Ai::Hunting { x: 2, y: 3 })));
+    }
+
+    #[test]
+    fn hurt_sleepers_wake_up_and_look_around() {
+        let mut world = world_with_orc(Ai::Asleep);
+        world.objects[1].fighter.as_mut().unwrap().hp -= 1;
+        world.step(Command::Wait);
+        assert!(matches!(world.objects[1].ai, Some(Ai::Searching { x: 17, y: 3, .. })));
+    }
+
+    #[test]
+    fn wanderers_hunt_the_player_once_they_hear_them() {
+        let mut world = world_with_orc(Ai::Wandering { x: 17, y: 3 });
+        world.step(Command::Wait);
+        assert!(matches!(world.objects[1].ai, Some(Ai::Wandering { .. })));
+
+        world.objects[1].set_pos(4, 3);
+        world.step(Command::Wait);
+        assert!(matches!(world.objects[1].ai, Some(Ai::Hunting { x: 2, y: 3 })));
+    }
+
+    #[test]
+    fn hunters_search_where_they_lost_the_player() {
+        let mut world = world_with_orc(Ai::Hunting { x: 13, y: 3 });
+        world.step(Command::Wait);
+        assert!(matches!(world.objects[1].ai, Some(Ai::Hunting { x: 13, y: 3 })));
+        for _ in 0..10 {
+            world.step(Command::Wait);
+            if matches!(world.objects[1].ai, Some(Ai::Searching { .. })) {
+                break;
+            }
+        }
+        assert_eq!(world.objects[1].pos(), (13, 3));
+        assert!(matches!(world.objects[1].ai, Some(Ai::Searching { x: 13, y: 3, .. })));
+    }
+
+    #[test]
+    fn searchers_give_up_and_wander_off() {
+        let mut world = world_with_orc(Ai::Searching { x: 17, y: 3, num_turns: 3 });
+        for _ in 0..3 {
+            world.step(Command::Wait);
+            assert!(matches!(world.objects[1].ai, Some(Ai::Searching { x: 17, y: 3, .. })));
+            assert!(world.objects[1].distance(17, 3) <= 2.0 * MONSTER_SEARCH_RADIUS as f32);
+        }
+        world.step(Command::Wait);
+        assert!(matches!(world.objects[1].ai, Some(Ai::Wandering { .. })));
+    }
+
+    #[test]
+    fn alerted_monsters_search_where_the_noise_was() {
+        let mut world = world_with_orc(Ai::Alerted { x: 13, y: 3, num_turns: ALARM_NUM_TURNS });
+        for _ in 0..10 {
+            world.step(Command::Wait);
+            if matches!(world.objects[1].ai, Some(Ai::Searching { .. })) {
+                break;
+            }
+        }
+        assert_eq!(world.objects[1].pos(), (13, 3));
+        assert!(matches!(world.objects[1].ai, Some(Ai::Searching { x: 13, y: 3, .. })));
+    }
+
+    #[test]
+    fn monsters_hear_through_closed_doors() {
+        let mut world = world_on(&["#######", "#..+..#", "#######"], (2, 1));
+        world.objects.push(still_orc(4, 1));
+        assert!(hears_player(1, &world.objects));
+        assert!(!sees_player(1, &world.objects, &world.game));
+        assert!(notices_player(1, &world.objects, &mut world.game));
+    }
+
+    #[test]
+    fn monsters_do_not_see_through_walls() {
+        let mut world = world_on(&["#########", "#...#...#", "#########"], (1, 1));
+        world.objects.push(still_orc(6, 1));
+        assert!(!hears_player(1, &world.objects));
+        assert!(!sees_player(1, &world.objects, &world.game));
+        for _ in 0..100 {
+            assert!(!notices_player(1, &world.objects, &mut world.game));
+        }
+
+        let mut open = world_on(&["#########", "#.......#", "#########"], (1, 1));
+        open.objects.push(still_orc(6, 1));
+        assert!(sees_player(1, &open.objects, &open.game));
+    }
+
+    #[test]
+    fn monsters_notice_the_player_in_sight_as_keen_as_they_are() {
+        let mut world = world_on(&["#########", "#.......#", "#########"], (1, 1));
+        world.objects.push(still_orc(6, 1));
+        for &perception in &[0, 60, 100] {
+            world.objects[1].senses.as_mut().unwrap().perception = perception;
+            let mut noticed = 0;
+            for _ in 0..200 {
+                let roll = world.game.rng.clone().gen_range(0, 100);
+                let notices = notices_player(1, &world.objects, &mut world.game);
+                assert_eq!(notices, roll < perception);
+                noticed += notices as u32;
+            }
+            match perception {
+                0 => assert_eq!(noticed, 0),
+                100 => assert_eq!(noticed, 200),
+                _ => assert!(noticed > 0 && noticed < 200),
+            }
+        }
+    }
+
+    /// A world with a nearly dead orc, out of sight of the player, and the
+    /// orc's index.
+    fn world_with_dying_orc() -> (World, usize) {
+        let mut world = World::new(1, GameMode::Exploration);
+        world.objects.truncate(1);
+        let (x, y) = world.player().pos();
+        let mut orc = make_monster("orc", x, y);
+        orc.fighter.as_mut().unwrap().hp = 1;
+        world.objects.push(orc);
+        (world, 1)
+    }
+
+    #[test]
+    fn monsters_killed_by_traps_are_no_kills() {
+        for &kind in &[TrapKind::Dart, TrapKind::Pit] {
+            let (mut world, orc) = world_with_dying_orc();
+            let (x, y) = world.objects[orc].pos();
+            world.game.map[x as usize][y as usize].trap = Some(Trap { kind: kind, known: false });
+            let log_start = world.game.log.len();
+            let xp = world.player().fighter.unwrap().xp;
+
+            spring_trap(orc, &mut world.objects, &mut world.game, &world.fov);
+            assert!(!world.objects[orc].alive);
+            assert_eq!(world.game.kills, 0);
+            assert_eq!(world.player().fighter.unwrap().xp, xp);
+            assert!(world.game.log[log_start..].iter().all(|&(ref message, _)| !message.contains("experience")));
+        }
+    }
+
+    #[test]
+    fn monsters_killed_by_lava_are_no_kills() {
+        let (mut world, orc) = world_with_dying_orc();
+        let (x, y) = world.objects[orc].pos();
+        world.game.map[x as usize][y as usize].set_terrain(Terrain::Lava);
+        let log_start = world.game.log.len();
+
+        burn(&mut world.objects, &mut world.game, &world.fov);
+        assert!(!world.objects[orc].alive);
+        assert_eq!(world.game.kills, 0);
+        assert!(world.game.log[log_start..].iter().all(|&(ref message, _)| !message.contains("experience")));
+    }
+}
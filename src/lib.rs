@@ -0,0 +1,149 @@
+//! Game logic for the Tombs of the Ancient Kings, independent of any
+//! front-end. A `World` is advanced one `Command` at a time, so it can be
+//! driven by the libtcod binary as well as by bots and tests.
+
+extern crate flate2;
+extern crate rand;
+extern crate rustc_serialize;
+
+mod binary;
+pub mod colors;
+pub mod fov;
+pub mod game;
+pub mod map;
+pub mod object;
+pub mod pathfinding;
+pub mod rng;
+pub mod save;
+pub mod scores;
+pub mod themes;
+pub mod vaults;
+
+pub use colors::Color;
+pub use game::{Command, Game, GameMode, Level, Stat, Turn, WizardCommand, World, PLAYER};
+pub use map::{map_size, Door, Generator, Map, Terrain, Tile};
+pub use object::{Ai, Equipment, Fighter, Item, MessageLog, Messages, Morale, Object, Projectile, Ranged, Senses,
+                 Slot, Targeting};
+pub use rng::{random_seed, GameRng};
+pub use save::{dump_save, list_slots, load_game, save_game, slot_path, SaveError, SaveFormat,
+               SaveHeader, SaveSlot};
+pub use themes::Theme;
+pub use vaults::{load_vaults, Vault};
+pub use scores::{high_scores_path, load_high_scores, morgue, record_high_score, write_morgue,
+                 HighScore};
+
+// size of the map, unless a game says otherwise
+pub const MAP_WIDTH: i32 = 80;
+pub const MAP_HEIGHT: i32 = 43;
+
+/// The depth the Amulet of the Ancient Kings lies on, unless a game says otherwise.
+pub const FINAL_DEPTH: u32 = 10;
+/// The depth of the boss's lair, unless a game says otherwise.
+pub const BOSS_DEPTH: u32 = 5;
+
+pub const FOV_LIGHT_WALLS: bool = true;
+pub const TORCH_RADIUS: i32 = 10;
+
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+/// How many rooms are tried on a map of the default size; bigger maps try
+/// proportionally more.
+const MAX_ROOMS: i32 = 30;
+
+/// The smallest piece of the map the BSP generator puts a room in.
+const BSP_MIN_LEAF_SIZE: i32 = 8;
+
+/// How likely a tile of a cave level starts out as a wall, in percent.
+const CAVE_WALL_CHANCE: u32 = 45;
+/// How many times the cellular automaton smooths a cave level.
+const CAVE_PASSES: usize = 7;
+/// How big a cave must be to make a level, as 1/N of the map; smaller ones
+/// are grown again.
+const CAVE_MIN_FLOOR_FRACTION: usize = 3;
+/// How likely a corridor going into a room gets a door there, in percent.
+const DOOR_CHANCE: u32 = 50;
+/// How likely a door is locked, in percent. Each locked door has a key.
+const LOCKED_DOOR_CHANCE: u32 = 10;
+
+/// How big a pool of lava or a chasm gets, in tiles walked over.
+const POOL_MIN_SIZE: usize = 8;
+const POOL_MAX_SIZE: usize = 20;
+/// How many tiles a rubble patch covers at most.
+const RUBBLE_MAX_SIZE: usize = 10;
+/// How likely a level has a shortcut behind a secret door, in percent, and
+/// how many steps it must save.
+const SECRET_SHORTCUT_CHANCE: u32 = 30;
+const SHORTCUT_MIN_SAVING: i32 = 20;
+/// How likely a level has a treasure room behind a secret door, in percent,
+/// and how many places are tried for it.
+const TREASURE_ROOM_CHANCE: u32 = 20;
+const TREASURE_ROOM_TRIES: usize = 50;
+
+/// How much of a cave counts as a room when spawning monsters and items.
+const CAVE_TILES_PER_AREA: usize = 80;
+
+const HEAL_AMOUNT: i32 = 40;
+
+const LIGHTNING_DAMAGE: i32 = 40;
+const LIGHTNING_RANGE: i32 = 5;
+
+const CONFUSE_RANGE: i32 = 8;
+const CONFUSE_NUM_TURNS: i32 = 10;
+
+const FIREBALL_RADIUS: i32 = 3;
+const FIREBALL_DAMAGE: i32 = 25;
+
+const DART_DAMAGE: i32 = 10;
+const PIT_DAMAGE: i32 = 5;
+/// How much standing in lava hurts, every turn.
+const LAVA_DAMAGE: i32 = 8;
+/// How far away monsters hear an alarm trap, and how long they look for it.
+const ALARM_RADIUS: i32 = 15;
+const ALARM_NUM_TURNS: i32 = 20;
+/// How far around the player searching looks, and how likely it finds each
+/// hidden trap or secret door there, in percent.
+const SEARCH_RADIUS: i32 = 2;
+const SEARCH_CHANCE: u32 = 50;
+/// How many turns searching takes.
+const SEARCH_TURNS: u32 = 3;
+/// How likely the player notices something hidden next to them in passing,
+/// every turn, in percent for each point of agility.
+const NOTICE_CHANCE_PER_AGILITY: u32 = 5;
+/// How many random places are tried when teleporting something.
+const FREE_SPOT_TRIES: usize = 1000;
+/// How many tiles a monster looks through for a way to its target before
+/// giving up and heading straight for it.
+const PATH_MAX_TILES: usize = 4000;
+/// How many turns' worth of detour a monster takes to get around another
+/// one in its way, rather than waiting for it to move.
+const PATH_OBSTACLE_COST: u32 = 8;
+/// How likely a new monster is to be asleep, or wandering around, in percent.
+const MONSTER_ASLEEP_CHANCE: u32 = 40;
+const MONSTER_WANDERING_CHANCE: u32 = 20;
+/// How much less likely a sleeping monster notices the player than an awake
+/// one, as a divisor of its perception.
+const ASLEEP_PERCEPTION_DIVISOR: u32 = 4;
+/// How long a monster that lost track of the player looks around for them,
+/// and how far from where it last saw them.
+const MONSTER_SEARCH_TURNS: i32 = 15;
+const MONSTER_SEARCH_RADIUS: i32 = 3;
+/// How close monsters that shoot let the player come before backing away.
+const KEEP_AWAY_DISTANCE: i32 = 3;
+/// How likely the monsters of a room are to form a pack, in percent.
+const PACK_CHANCE: u32 = 30;
+/// How long monsters run when hurt, or when their pack's leader dies, at
+/// the least.
+const FLEE_TURNS: i32 = 10;
+const PACK_BREAK_TURNS: i32 = 20;
+/// How often monsters on the run get a hit point back: one turn in this
+/// many, on average.
+const FLEE_REGEN_TURNS: u32 = 3;
+/// How much farther than the player can walk fleeing monsters reckon they
+/// could get, in percent. Above 100, they would rather slip past the player
+/// to open ground than run into a dead end.
+const FLEE_SAFETY_FACTOR: i32 = 120;
+
+const LEVEL_UP_BASE: i32 = 200;
+const LEVEL_UP_FACTOR: i32 = 150;
+
+const VICTORY_BONUS: u32 = 1000;
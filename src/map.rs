@@ -0,0 +1,1498 @@
+use std::cmp;
+
+use rand::Rng;
+
+use colors::{self, Color};
+use game::PLAYER;
+use object::{Ai, DeathCallback, Equipment, Fighter, Item, Morale, Object, Projectile, Ranged, Senses, Slot};
+use rng::GameRng;
+use themes::Theme;
+use vaults::{place_vaults, Vault};
+use {BSP_MIN_LEAF_SIZE, CAVE_MIN_FLOOR_FRACTION, CAVE_PASSES, CAVE_TILES_PER_AREA, CAVE_WALL_CHANCE, DOOR_CHANCE,
+     LAVA_DAMAGE, LOCKED_DOOR_CHANCE, MAP_HEIGHT, MAP_WIDTH, MAX_ROOMS, MONSTER_ASLEEP_CHANCE,
+     MONSTER_WANDERING_CHANCE, PACK_CHANCE, POOL_MAX_SIZE, POOL_MIN_SIZE, ROOM_MAX_SIZE, ROOM_MIN_SIZE, RUBBLE_MAX_SIZE,
+     SECRET_SHORTCUT_CHANCE, SHORTCUT_MIN_SAVING, TREASURE_ROOM_CHANCE, TREASURE_ROOM_TRIES};
+
+pub type Map = Vec<Vec<Tile>>;
+
+pub struct Rect {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Rect { x1: x, y1: y, x2: x + w, y2: y + h }
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) / 2;
+        let center_y = (self.y1 + self.y2) / 2;
+        (center_x, center_y)
+    }
+
+    /// The floor tiles of a room carved out of this rectangle.
+    pub fn tiles(&self) -> Vec<(i32, i32)> {
+        let mut tiles = vec![];
+        for x in (self.x1 + 1) .. self.x2 {
+            for y in (self.y1 + 1) .. self.y2 {
+                tiles.push((x, y));
+            }
+        }
+        tiles
+    }
+
+    pub fn intersects_with(&self, other: &Rect) -> bool {
+        (self.x1 <= other.x2) && (self.x2 >= other.x1) &&
+            (self.y1 <= other.y2) && (self.y2 >= other.y1)
+    }
+}
+
+/// A tile of the map and its properties
+#[derive(Clone, Copy, Debug, RustcEncodable, RustcDecodable)]
+pub struct Tile {
+    pub terrain: Terrain,
+    pub blocked: bool,
+    pub block_sight: bool,
+    pub explored: bool,
+    pub door: Option<Door>,
+    pub trap: Option<Trap>,
+}
+
+/// What the ground of a tile is made of.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Terrain {
+    Wall,
+    Floor,
+    /// Slow to wade through.
+    Water,
+    /// Burns whatever stands in it.
+    Lava,
+    /// Drops the player to the level below.
+    Chasm,
+    /// Slow to climb over.
+    Rubble,
+}
+
+impl Terrain {
+    pub fn name(self) -> &'static str {
+        match self {
+            Terrain::Wall => "wall",
+            Terrain::Floor => "floor",
+            Terrain::Water => "water",
+            Terrain::Lava => "lava",
+            Terrain::Chasm => "chasm",
+            Terrain::Rubble => "rubble",
+        }
+    }
+
+    /// How many turns it takes to step onto the terrain.
+    pub fn movement_cost(self) -> u32 {
+        match self {
+            Terrain::Water | Terrain::Rubble => 2,
+            _ => 1,
+        }
+    }
+
+    /// The damage done to whatever stands in the terrain, every turn.
+    pub fn damage_per_turn(self) -> i32 {
+        match self {
+            Terrain::Lava => LAVA_DAMAGE,
+            _ => 0,
+        }
+    }
+
+    pub fn blocks(self) -> bool {
+        self == Terrain::Wall
+    }
+
+    pub fn blocks_sight(self) -> bool {
+        self == Terrain::Wall
+    }
+
+    /// Whether stepping onto the terrain does harm. Monsters keep out of it.
+    pub fn is_hazard(self) -> bool {
+        self.damage_per_turn() > 0 || self == Terrain::Chasm
+    }
+
+    /// The background of the terrain when it's out of sight.
+    pub fn dark_color(self) -> Color {
+        match self {
+            Terrain::Wall => Color { r: 0, g: 0, b: 100 },
+            Terrain::Floor => Color { r: 50, g: 50, b: 150 },
+            Terrain::Water => Color { r: 20, g: 40, b: 170 },
+            Terrain::Lava => Color { r: 100, g: 30, b: 60 },
+            Terrain::Chasm => Color { r: 10, g: 10, b: 40 },
+            Terrain::Rubble => Color { r: 40, g: 40, b: 120 },
+        }
+    }
+
+    /// The background of the terrain in view.
+    pub fn light_color(self) -> Color {
+        match self {
+            Terrain::Wall => Color { r: 130, g: 110, b: 50 },
+            Terrain::Floor => Color { r: 200, g: 180, b: 50 },
+            Terrain::Water => Color { r: 40, g: 90, b: 220 },
+            Terrain::Lava => Color { r: 230, g: 80, b: 20 },
+            Terrain::Chasm => Color { r: 25, g: 20, b: 15 },
+            Terrain::Rubble => Color { r: 150, g: 130, b: 70 },
+        }
+    }
+
+    /// How the terrain is drawn in plain text.
+    pub fn glyph(self) -> char {
+        match self {
+            Terrain::Wall => '#',
+            Terrain::Floor => '.',
+            Terrain::Water => '~',
+            Terrain::Lava => '=',
+            Terrain::Chasm => ':',
+            Terrain::Rubble => ',',
+        }
+    }
+}
+
+/// The state of a door. Closed doors block movement and sight; locked ones
+/// need a key to open.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Door {
+    Open,
+    Closed,
+    Locked,
+    /// Looks like wall until found, and is a closed door from then on.
+    Secret,
+}
+
+/// A trap, sprung by whatever walks onto it.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Trap {
+    pub kind: TrapKind,
+    /// Whether the player knows about the trap. Unknown traps aren't shown.
+    pub known: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum TrapKind {
+    /// Shoots a dart at whoever steps on it.
+    Dart,
+    /// Sends whoever steps on it somewhere else on the level.
+    Teleport,
+    /// Calls the monsters around.
+    Alarm,
+    /// Drops the player to the level below.
+    Pit,
+}
+
+impl TrapKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            TrapKind::Dart => "dart trap",
+            TrapKind::Teleport => "teleport trap",
+            TrapKind::Alarm => "alarm trap",
+            TrapKind::Pit => "pit",
+        }
+    }
+}
+
+impl Tile {
+    pub fn new(terrain: Terrain) -> Self {
+        Tile{terrain: terrain, blocked: terrain.blocks(), block_sight: terrain.blocks_sight(), explored: false,
+             door: None, trap: None}
+    }
+
+    pub fn empty() -> Self {
+        Tile::new(Terrain::Floor)
+    }
+
+    pub fn wall() -> Self {
+        Tile::new(Terrain::Wall)
+    }
+
+    /// A doorway on the floor, with a door in it.
+    pub fn door(door: Door) -> Self {
+        let mut tile = Tile::empty();
+        tile.set_door(door);
+        tile
+    }
+
+    /// Open, close, lock or hide the door on this tile.
+    pub fn set_door(&mut self, door: Door) {
+        // a secret door is part of the wall until it's found
+        self.set_terrain(if door == Door::Secret { Terrain::Wall } else { Terrain::Floor });
+        self.door = Some(door);
+        self.blocked = door != Door::Open;
+        self.block_sight = door != Door::Open;
+    }
+
+    /// Whether the tile can be walked on, if need be after opening a door.
+    pub fn is_passable(&self) -> bool {
+        !self.blocked || self.door.is_some()
+    }
+
+    /// Change what the ground of the tile is made of.
+    pub fn set_terrain(&mut self, terrain: Terrain) {
+        self.terrain = terrain;
+        self.blocked = terrain.blocks();
+        self.block_sight = terrain.blocks_sight();
+    }
+}
+
+pub struct Transition {
+    pub level: u32,
+    pub value: u32,
+}
+
+pub fn from_dungeon_level(table: &[Transition], level: u32) -> u32 {
+    table.iter()
+        .rev()
+        .find(|transition| level >= transition.level)
+        .map_or(0, |transition| transition.value)
+}
+
+/// The width and height of a map.
+pub fn map_size(map: &Map) -> (i32, i32) {
+    (map.len() as i32, map.first().map_or(0, |column| column.len() as i32))
+}
+
+/// A map drawn row by row, for tests: terrain as `Terrain::glyph` draws
+/// it, `+` for a closed door, `'` for an open one and `x` for a locked one.
+#[cfg(test)]
+pub fn drawn_map(rows: &[&str]) -> Map {
+    let terrains = [Terrain::Wall, Terrain::Floor, Terrain::Water, Terrain::Lava, Terrain::Chasm, Terrain::Rubble];
+    let mut map = vec![vec![Tile::wall(); rows.len()]; rows[0].len()];
+    for (y, row) in rows.iter().enumerate() {
+        for (x, glyph) in row.chars().enumerate() {
+            map[x][y] = match glyph {
+                '+' => Tile::door(Door::Closed),
+                '\'' => Tile::door(Door::Open),
+                'x' => Tile::door(Door::Locked),
+                _ => match terrains.iter().find(|terrain| terrain.glyph() == glyph) {
+                    Some(&terrain) => Tile::new(terrain),
+                    None => panic!("unknown glyph '{}'", glyph),
+                },
+            };
+        }
+    }
+    map
+}
+
+pub fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    if map[x as usize][y as usize].blocked {
+        return true;
+    }
+
+    objects.iter().any(|object| {
+        object.blocks && object.pos() == (x, y)
+    })
+}
+
+/// The algorithms levels can be made with.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Generator {
+    /// Random non-overlapping rooms, each joined to the one before.
+    Rooms,
+    /// Binary space partition: rooms packed into a recursively split map.
+    Bsp,
+    /// Organic caves grown by a cellular automaton.
+    Caves,
+    /// The boss's lair: a pillared hall at the end of a corridor.
+    Lair,
+}
+
+/// What a generator leaves behind besides the tiles: the areas to spawn
+/// monsters and items in, and where the stairs go.
+struct Layout {
+    areas: Vec<Vec<(i32, i32)>>,
+    /// The rooms of the level, which get doors. Caves have none.
+    rooms: Vec<Rect>,
+    up_stairs: (i32, i32),
+    down_stairs: (i32, i32),
+}
+
+impl Layout {
+    /// Rooms are spawned in one by one, and the stairs are in the first and
+    /// the last room.
+    fn from_rooms(rooms: Vec<Rect>) -> Self {
+        Layout {
+            areas: rooms.iter().map(Rect::tiles).collect(),
+            up_stairs: rooms[0].center(),
+            down_stairs: rooms[rooms.len() - 1].center(),
+            rooms: rooms,
+        }
+    }
+}
+
+/// Which generator makes a level: the deeper, the more likely BSP floors and
+/// caves get.
+pub fn choose_generator(level: u32, rng: &mut GameRng) -> Generator {
+    use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+
+    let generator_chances = &mut [
+        Weighted { weight: from_dungeon_level(&[
+            Transition { level: 1, value: 70 },
+            Transition { level: 4, value: 40 },
+            Transition { level: 7, value: 20 },
+            ], level), item: Generator::Rooms },
+        Weighted { weight: from_dungeon_level(&[
+            Transition { level: 1, value: 30 },
+            Transition { level: 4, value: 60 },
+            Transition { level: 7, value: 60 },
+            ], level), item: Generator::Bsp },
+        Weighted { weight: from_dungeon_level(&[
+            Transition { level: 3, value: 15 },
+            Transition { level: 5, value: 30 },
+            Transition { level: 7, value: 40 },
+            ], level), item: Generator::Caves },
+    ];
+    WeightedChoice::new(generator_chances).ind_sample(rng)
+}
+
+pub fn make_map(objects: &mut Vec<Object>, level: u32, theme: Theme, size: (i32, i32), vaults: &[Vault],
+        rng: &mut GameRng) -> Map {
+    let generator = theme.generator(level, rng);
+    make_map_with(generator, objects, level, theme, size, vaults, rng)
+}
+
+/// Make a level with a given generator, whatever the depth and the theme.
+pub fn make_map_with(generator: Generator, objects: &mut Vec<Object>, level: u32, theme: Theme,
+        (width, height): (i32, i32), vaults: &[Vault], rng: &mut GameRng) -> Map {
+    // fill map with "unblocked" tiles
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+
+    assert_eq!(&objects[PLAYER] as *const _, &objects[0] as *const _);
+    objects.truncate(1);
+
+    let layout = match generator {
+        Generator::Rooms => Layout::from_rooms(make_random_rooms(&mut map, rng)),
+        Generator::Bsp => Layout::from_rooms(make_bsp_rooms(&mut map, rng)),
+        Generator::Caves => make_caves(&mut map, rng),
+        Generator::Lair => make_lair(&mut map, rng),
+    };
+    // vaults only go into solid rock, so they're never part of the areas
+    place_vaults(vaults, &mut map, objects, level, rng);
+    // everything is placed on the floor, so with all the floor reachable from
+    // the start, so are the stairs, the monsters and the items
+    connect_regions(&mut map, layout.up_stairs, rng);
+    place_terrain(&layout, &mut map, objects, level, theme, rng);
+    if generator == Generator::Lair {
+        // the boss stands guard in front of the way down
+        let (x, y) = (layout.down_stairs.0 - 1, layout.down_stairs.1);
+        map[x as usize][y as usize].set_terrain(Terrain::Floor);
+        objects.push(make_monster("orc warlord", x, y));
+    }
+    let monsters_from = objects.len();
+    // the player arrives on the stairs
+    let stairs = [layout.up_stairs, layout.down_stairs];
+    for area in &layout.areas {
+        place_objects(area, &stairs, &mut map, objects, level, theme, rng);
+    }
+    if generator == Generator::Lair {
+        // everybody in the lair follows the boss
+        form_pack(&mut objects[monsters_from - 1 ..]);
+    }
+    place_doors(&layout, &mut map, objects, rng);
+    // after the keys, so none is hidden away
+    place_secrets(&layout, &mut map, objects, rng);
+
+    // nobody should spring a trap arriving on a level
+    for &(x, y) in &[layout.up_stairs, layout.down_stairs] {
+        map[x as usize][y as usize].trap = None;
+    }
+
+    let starting_position = layout.up_stairs;
+    let mut up_stairs = Object::new(starting_position.0, starting_position.1, '<', "up stairs",
+                                    colors::WHITE, false);
+    up_stairs.always_visible = true;
+    objects.push(up_stairs);
+
+    let (down_x, down_y) = layout.down_stairs;
+    let mut down_stairs = Object::new(down_x, down_y, '>', "down stairs", colors::WHITE, false);
+    down_stairs.always_visible = true;
+    objects.push(down_stairs);
+    objects[PLAYER].set_pos(starting_position.0, starting_position.1);
+
+    map
+}
+
+/// Carve up to `MAX_ROOMS` (more on big maps) random rooms that don't overlap, each joined to
+/// the one before it.
+fn make_random_rooms(map: &mut Map, rng: &mut GameRng) -> Vec<Rect> {
+    let mut rooms: Vec<Rect> = vec![];
+    let (width, height) = map_size(map);
+    let max_rooms = cmp::max(1, MAX_ROOMS * width * height / (MAP_WIDTH * MAP_HEIGHT));
+
+    for _ in 0 .. max_rooms {
+        let w = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+        let h = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+
+        let x = rng.gen_range(0, width - w);
+        let y = rng.gen_range(0, height - h);
+
+        let new_room = Rect::new(x, y, w, h);
+
+        let failed = rooms.iter().any(|other_room| new_room.intersects_with(other_room));
+
+        if !failed {
+            create_room(&new_room, map);
+            if let Some(prev_room) = rooms.last() {
+                connect(prev_room.center(), new_room.center(), map, rng);
+            }
+            rooms.push(new_room);
+        }
+    }
+    rooms
+}
+
+/// Split the map in two, and each half in two again, until the pieces get
+/// too small to split; then carve a room in each piece and join the rooms of
+/// every two halves. Every bit of the map gets used, and no room is far from
+/// its neighbours.
+fn make_bsp_rooms(map: &mut Map, rng: &mut GameRng) -> Vec<Rect> {
+    let mut rooms = vec![];
+    // leave the outermost tiles as walls, like the random rooms do
+    let (width, height) = map_size(map);
+    let whole_map = Rect::new(0, 0, width - 1, height - 1);
+    split_area(&whole_map, map, &mut rooms, rng);
+    rooms
+}
+
+/// Fill an area with rooms, returning the index of one of them to join the
+/// area to its sibling with.
+fn split_area(area: &Rect, map: &mut Map, rooms: &mut Vec<Rect>, rng: &mut GameRng) -> usize {
+    let (width, height) = (area.x2 - area.x1, area.y2 - area.y1);
+    let can_split_across = height >= 2 * BSP_MIN_LEAF_SIZE;
+    let can_split_down = width >= 2 * BSP_MIN_LEAF_SIZE;
+
+    if !can_split_across && !can_split_down {
+        let w = rng.gen_range(ROOM_MIN_SIZE, cmp::min(width, ROOM_MAX_SIZE) + 1);
+        let h = rng.gen_range(ROOM_MIN_SIZE, cmp::min(height, ROOM_MAX_SIZE) + 1);
+        let x = rng.gen_range(area.x1, area.x2 - w + 1);
+        let y = rng.gen_range(area.y1, area.y2 - h + 1);
+        let room = Rect::new(x, y, w, h);
+        create_room(&room, map);
+        rooms.push(room);
+        return rooms.len() - 1;
+    }
+
+    // split the long side of an area, so the pieces don't get too thin
+    let split_down = if can_split_across && can_split_down {
+        if width * 4 > height * 5 {
+            true
+        } else if height * 4 > width * 5 {
+            false
+        } else {
+            rng.gen()
+        }
+    } else {
+        can_split_down
+    };
+    let (first, second) = if split_down {
+        let x = rng.gen_range(area.x1 + BSP_MIN_LEAF_SIZE, area.x2 - BSP_MIN_LEAF_SIZE + 1);
+        (Rect::new(area.x1, area.y1, x - area.x1, height), Rect::new(x, area.y1, area.x2 - x, height))
+    } else {
+        let y = rng.gen_range(area.y1 + BSP_MIN_LEAF_SIZE, area.y2 - BSP_MIN_LEAF_SIZE + 1);
+        (Rect::new(area.x1, area.y1, width, y - area.y1), Rect::new(area.x1, y, width, area.y2 - y))
+    };
+
+    let first_room = split_area(&first, map, rooms, rng);
+    let second_room = split_area(&second, map, rooms, rng);
+    let (first_center, second_center) = (rooms[first_room].center(), rooms[second_room].center());
+    connect(first_center, second_center, map, rng);
+
+    if rng.gen() { first_room } else { second_room }
+}
+
+/// Grow caves: start from random noise, then repeatedly turn every tile into
+/// a wall if most of its neighbours are walls, and into floor otherwise.
+/// Only the biggest cave is kept, so every part of the level can be reached.
+fn make_caves(map: &mut Map, rng: &mut GameRng) -> Layout {
+    let (width, height) = map_size(map);
+    let floor = loop {
+        for x in 1 .. width - 1 {
+            for y in 1 .. height - 1 {
+                map[x as usize][y as usize] = if rng.gen_range(0, 100) < CAVE_WALL_CHANCE {
+                    Tile::wall()
+                } else {
+                    Tile::empty()
+                };
+            }
+        }
+        // the first passes also fill in the middle of big open spaces
+        for pass in 0 .. CAVE_PASSES {
+            let fill_open_spaces = pass < CAVE_PASSES - 3;
+            let walls_near: Vec<Vec<(i32, i32)>> = (0 .. width).map(|x| {
+                (0 .. height).map(|y| (walls_within(map, x, y, 1), walls_within(map, x, y, 2)))
+                    .collect()
+            }).collect();
+            for x in 1 .. width - 1 {
+                for y in 1 .. height - 1 {
+                    let (walls_1, walls_2) = walls_near[x as usize][y as usize];
+                    let wall = walls_1 >= 5 || (fill_open_spaces && walls_2 <= 2);
+                    map[x as usize][y as usize] = if wall { Tile::wall() } else { Tile::empty() };
+                }
+            }
+        }
+
+        // keep the biggest cave, and wall up the rest
+        let mut caves = vec![];
+        let mut seen = vec![vec![false; height as usize]; width as usize];
+        for x in 0 .. width {
+            for y in 0 .. height {
+                if !map[x as usize][y as usize].blocked && !seen[x as usize][y as usize] {
+                    let cave = flood_fill(map, (x, y));
+                    for &(x, y) in &cave {
+                        seen[x as usize][y as usize] = true;
+                    }
+                    caves.push(cave);
+                }
+            }
+        }
+        caves.sort_by_key(|cave| cave.len());
+        let biggest = caves.pop().unwrap_or_default();
+        if biggest.len() >= (width * height) as usize / CAVE_MIN_FLOOR_FRACTION {
+            for cave in &caves {
+                for &(x, y) in cave {
+                    map[x as usize][y as usize] = Tile::wall();
+                }
+            }
+            break biggest;
+        }
+    };
+
+    // the stairs go as far apart as the cave allows
+    let up_stairs = floor[rng.gen_range(0, floor.len())];
+    let down_stairs = *flood_fill(map, up_stairs).last().unwrap();
+
+    // a room's worth of cave around each of a few random spots
+    let mut seeds = vec![];
+    while seeds.len() < floor.len() / CAVE_TILES_PER_AREA {
+        let seed = floor[rng.gen_range(0, floor.len())];
+        if !seeds.contains(&seed) {
+            seeds.push(seed);
+        }
+    }
+    let areas = areas_around(map, &seeds);
+    Layout { areas: areas, rooms: vec![], up_stairs: up_stairs, down_stairs: down_stairs }
+}
+
+/// Share out the floor between `seeds`: every tile goes to the seed the
+/// fewest steps away from it, so each area is one stretch of floor.
+fn areas_around(map: &Map, seeds: &[(i32, i32)]) -> Vec<Vec<(i32, i32)>> {
+    let (width, height) = map_size(map);
+    let mut area_of = vec![vec![None; height as usize]; width as usize];
+    let mut tiles = vec![];
+    for (area, &(x, y)) in seeds.iter().enumerate() {
+        area_of[x as usize][y as usize] = Some(area);
+        tiles.push((x, y));
+    }
+    let mut areas = vec![vec![]; seeds.len()];
+    let mut next = 0;
+    while next < tiles.len() {
+        let (x, y) = tiles[next];
+        next += 1;
+        let area = area_of[x as usize][y as usize].unwrap();
+        areas[area].push((x, y));
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                if area_of[x as usize][y as usize].is_none() && !map[x as usize][y as usize].blocked {
+                    area_of[x as usize][y as usize] = Some(area);
+                    tiles.push((x, y));
+                }
+            }
+        }
+    }
+    areas
+}
+
+/// A small room to arrive in, and a corridor from it to a great hall held
+/// up by pillars, with the way down at its far end.
+fn make_lair(map: &mut Map, rng: &mut GameRng) -> Layout {
+    let (width, height) = map_size(map);
+    let entrance = Rect::new(1, height / 2 - 3, 7, 6);
+    let hall = Rect::new(width / 3, 1, width - width / 3 - 2, height - 3);
+    create_room(&entrance, map);
+    create_room(&hall, map);
+    connect(entrance.center(), hall.center(), map, rng);
+
+    // rows of pillars, leaving an aisle down the middle
+    let (_, aisle) = hall.center();
+    for (x, y) in hall.tiles() {
+        let pillar = (x - hall.x1) % 4 == 3 && (y - hall.y1) % 4 == 3;
+        if pillar && x < hall.x2 - 3 && y < hall.y2 - 1 && y != aisle {
+            map[x as usize][y as usize] = Tile::wall();
+        }
+    }
+
+    // the hall is big enough for a few rooms' worth of monsters
+    Layout {
+        areas: (0 .. 4).map(|_| hall.tiles()).collect(),
+        up_stairs: entrance.center(),
+        down_stairs: (hall.x2 - 2, aisle),
+        rooms: vec![entrance, hall],
+    }
+}
+
+/// How many tiles within `radius` steps of a tile, itself included, are
+/// walls. Tiles off the map count as walls.
+fn walls_within(map: &Map, x: i32, y: i32, radius: i32) -> i32 {
+    let (width, height) = map_size(map);
+    let mut walls = 0;
+    for dx in -radius .. radius + 1 {
+        for dy in -radius .. radius + 1 {
+            let (x, y) = (x + dx, y + dy);
+            if x < 0 || y < 0 || x >= width || y >= height || map[x as usize][y as usize].blocked {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+/// All the tiles that can be walked to from `start`, doors included,
+/// nearest first. Moves can be diagonal, as they are for the player.
+pub fn flood_fill(map: &Map, start: (i32, i32)) -> Vec<(i32, i32)> {
+    flood_fill_where(map, start, Tile::is_passable)
+}
+
+/// The tiles reachable from `start` going only over tiles for which
+/// `passable` is true, nearest first.
+fn flood_fill_where<F: Fn(&Tile) -> bool>(map: &Map, start: (i32, i32), passable: F) -> Vec<(i32, i32)> {
+    let (width, height) = map_size(map);
+    let mut seen = vec![vec![false; height as usize]; width as usize];
+    let mut tiles = vec![start];
+    seen[start.0 as usize][start.1 as usize] = true;
+    let mut next = 0;
+    while next < tiles.len() {
+        let (x, y) = tiles[next];
+        next += 1;
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                if !seen[x as usize][y as usize] && passable(&map[x as usize][y as usize]) {
+                    seen[x as usize][y as usize] = true;
+                    tiles.push((x, y));
+                }
+            }
+        }
+    }
+    tiles
+}
+
+/// Tunnel from the floor that can be reached from `start` to the nearest
+/// floor that can't, until all of it can be reached.
+fn connect_regions(map: &mut Map, start: (i32, i32), rng: &mut GameRng) {
+    let (width, height) = map_size(map);
+    let mut reached = vec![vec![false; height as usize]; width as usize];
+    reach_from(map, start, &mut reached);
+    loop {
+        // a search out from all the floor reached at once, through the rock,
+        // remembering which reached tile each way started at
+        let mut origin = vec![vec![None; height as usize]; width as usize];
+        let mut tiles = vec![];
+        for x in 0 .. width {
+            for y in 0 .. height {
+                if reached[x as usize][y as usize] {
+                    origin[x as usize][y as usize] = Some((x, y));
+                    tiles.push((x, y));
+                }
+            }
+        }
+        let mut next = 0;
+        let cut_off = loop {
+            let (x, y) = match tiles.get(next) {
+                Some(&tile) => tile,
+                None => return,
+            };
+            next += 1;
+            if !map[x as usize][y as usize].blocked && !reached[x as usize][y as usize] {
+                break (x, y);
+            }
+            for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                let (next_x, next_y) = (x + dx, y + dy);
+                if next_x < 0 || next_y < 0 || next_x >= width || next_y >= height {
+                    continue;
+                }
+                if origin[next_x as usize][next_y as usize].is_none() {
+                    origin[next_x as usize][next_y as usize] = origin[x as usize][y as usize];
+                    tiles.push((next_x, next_y));
+                }
+            }
+        };
+
+        let from = origin[cut_off.0 as usize][cut_off.1 as usize].unwrap();
+        connect(from, cut_off, map, rng);
+        // the tunnel and whatever it joined up with
+        reach_from(map, cut_off, &mut reached);
+    }
+}
+
+/// Mark the tiles that can be walked to from `start` as reached, without
+/// going over those already reached.
+fn reach_from(map: &Map, start: (i32, i32), reached: &mut [Vec<bool>]) {
+    let (width, height) = map_size(map);
+    let mut tiles = vec![start];
+    reached[start.0 as usize][start.1 as usize] = true;
+    while let Some((x, y)) = tiles.pop() {
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                if !reached[x as usize][y as usize] && map[x as usize][y as usize].is_passable() {
+                    reached[x as usize][y as usize] = true;
+                    tiles.push((x, y));
+                }
+            }
+        }
+    }
+}
+
+/// Change the ground in parts of a fresh level, as much as its theme wants:
+/// rubble, rivers, pools of water and lava, and chasms. None of it goes on
+/// the stairs or under what the vaults hold, and no chasm or lava cuts any
+/// floor off from the up stairs.
+fn place_terrain(layout: &Layout, map: &mut Map, objects: &[Object], level: u32, theme: Theme,
+        rng: &mut GameRng) {
+    // the player isn't on the level yet
+    let keep_clear: Vec<_> = [layout.up_stairs, layout.down_stairs].iter().cloned()
+        .chain(objects[PLAYER + 1 ..].iter().map(Object::pos))
+        .collect();
+    let chances = theme.terrain_chances(level);
+
+    for _ in 0 .. rng.gen_range(0, chances.rubble_patches + 1) {
+        let size = rng.gen_range(1, RUBBLE_MAX_SIZE + 1);
+        spread(Terrain::Rubble, size, &keep_clear, map, rng);
+    }
+    if rng.gen_range(0, 100) < chances.river {
+        make_river(&keep_clear, map, rng);
+    }
+    for _ in 0 .. rng.gen_range(0, chances.water_pools + 1) {
+        let size = rng.gen_range(POOL_MIN_SIZE, POOL_MAX_SIZE + 1);
+        spread(Terrain::Water, size, &keep_clear, map, rng);
+    }
+
+    let mut hazards = vec![];
+    if rng.gen_range(0, 100) < chances.chasm {
+        hazards.push(Terrain::Chasm);
+    }
+    for _ in 0 .. rng.gen_range(0, chances.lava_pools + 1) {
+        hazards.push(Terrain::Lava);
+    }
+    for terrain in hazards {
+        let size = rng.gen_range(POOL_MIN_SIZE, POOL_MAX_SIZE + 1);
+        let pool = spread(terrain, size, &keep_clear, map, rng);
+        if cuts_off_floor(map, layout.up_stairs) {
+            for &(x, y) in &pool {
+                map[x as usize][y as usize].set_terrain(Terrain::Floor);
+            }
+        }
+    }
+}
+
+/// Floor with nothing built on it, which other terrain can replace.
+fn is_bare_floor(map: &Map, (x, y): (i32, i32)) -> bool {
+    let tile = &map[x as usize][y as usize];
+    tile.terrain == Terrain::Floor && tile.door.is_none()
+}
+
+/// Turn up to `size` tiles of floor into another terrain, wandering at
+/// random from somewhere on the floor. Returns the tiles changed.
+fn spread(terrain: Terrain, size: usize, keep_clear: &[(i32, i32)], map: &mut Map,
+        rng: &mut GameRng) -> Vec<(i32, i32)> {
+    let (width, height) = map_size(map);
+    let floor: Vec<_> = (0 .. width)
+        .flat_map(|x| (0 .. height).map(move |y| (x, y)))
+        .filter(|&tile| is_bare_floor(map, tile))
+        .collect();
+    if floor.is_empty() {
+        return vec![];
+    }
+    let (mut x, mut y) = floor[rng.gen_range(0, floor.len())];
+    let mut changed = vec![];
+    // a walk stuck in a corner of the floor gives up eventually
+    for _ in 0 .. size * 4 {
+        if changed.len() >= size {
+            break;
+        }
+        if is_bare_floor(map, (x, y)) && !keep_clear.contains(&(x, y)) {
+            map[x as usize][y as usize].set_terrain(terrain);
+            changed.push((x, y));
+        }
+        x = cmp::max(1, cmp::min(width - 2, x + rng.gen_range(-1, 2)));
+        y = cmp::max(1, cmp::min(height - 2, y + rng.gen_range(-1, 2)));
+    }
+    changed
+}
+
+/// Run a winding river across the level, two tiles wide. It only floods the
+/// floor on its way, so it goes underground through the rock.
+fn make_river(keep_clear: &[(i32, i32)], map: &mut Map, rng: &mut GameRng) {
+    let (width, height) = map_size(map);
+    let across: bool = rng.gen();
+    let (length, breadth) = if across { (width, height) } else { (height, width) };
+    let mut middle = rng.gen_range(breadth / 4, breadth * 3 / 4);
+    for along in 1 .. length - 1 {
+        middle = cmp::max(1, cmp::min(breadth - 3, middle + rng.gen_range(-1, 2)));
+        for side in middle .. middle + 2 {
+            let (x, y) = if across { (along, side) } else { (side, along) };
+            if is_bare_floor(map, (x, y)) && !keep_clear.contains(&(x, y)) {
+                map[x as usize][y as usize].set_terrain(Terrain::Water);
+            }
+        }
+    }
+}
+
+/// Whether some of the floor can only be reached from `start` through lava
+/// or a chasm.
+fn cuts_off_floor(map: &Map, start: (i32, i32)) -> bool {
+    let safe = |tile: &Tile| tile.is_passable() && !tile.terrain.is_hazard();
+    let reached = flood_fill_where(map, start, safe).len();
+    let floor = map.iter().flat_map(|column| column.iter()).filter(|tile| safe(tile)).count();
+    reached < floor
+}
+
+/// Put doors in some of the doorways of the rooms, and lock a few of them.
+/// A locked door never shuts the down stairs away from the up stairs, and
+/// each one has its key somewhere that can be reached without it.
+fn place_doors(layout: &Layout, map: &mut Map, objects: &mut Vec<Object>, rng: &mut GameRng) {
+    let mut doors = vec![];
+    for room in &layout.rooms {
+        for (x, y) in doorways(room, map) {
+            if !doors.contains(&(x, y)) && rng.gen_range(0, 100) < DOOR_CHANCE {
+                map[x as usize][y as usize] = Tile::door(Door::Closed);
+                doors.push((x, y));
+            }
+        }
+    }
+
+    let without_key = |tile: &Tile| {
+        tile.is_passable() && tile.door != Some(Door::Locked) && !tile.terrain.is_hazard()
+    };
+    let mut locked = 0;
+    for &(x, y) in &doors {
+        if rng.gen_range(0, 100) >= LOCKED_DOOR_CHANCE {
+            continue;
+        }
+        map[x as usize][y as usize].set_door(Door::Locked);
+        if flood_fill_where(map, layout.up_stairs, without_key).contains(&layout.down_stairs) {
+            locked += 1;
+        } else {
+            map[x as usize][y as usize].set_door(Door::Closed);
+        }
+    }
+
+    // not on the stairs either, which aren't placed yet
+    let mut spots: Vec<_> = flood_fill_where(map, layout.up_stairs, without_key).into_iter()
+        .filter(|&(x, y)| !is_blocked(x, y, map, objects))
+        .filter(|&spot| spot != layout.up_stairs && spot != layout.down_stairs)
+        .collect();
+    for _ in 0 .. cmp::min(locked, spots.len()) {
+        let (x, y) = spots.swap_remove(rng.gen_range(0, spots.len()));
+        objects.push(make_item(Item::Key, x, y));
+    }
+}
+
+/// Now and then, hide a shortcut or a treasure room behind a secret door.
+fn place_secrets(layout: &Layout, map: &mut Map, objects: &mut Vec<Object>, rng: &mut GameRng) {
+    if rng.gen_range(0, 100) < SECRET_SHORTCUT_CHANCE {
+        hide_shortcut(layout.up_stairs, map, rng);
+    }
+    if rng.gen_range(0, 100) < TREASURE_ROOM_CHANCE {
+        hide_treasure_room(map, objects, rng);
+    }
+}
+
+/// Floor that can be walked on safely.
+fn is_safe(tile: &Tile) -> bool {
+    tile.is_passable() && !tile.terrain.is_hazard()
+}
+
+/// Wall with nothing hidden in it.
+fn is_rock(tile: &Tile) -> bool {
+    tile.terrain == Terrain::Wall && tile.door.is_none()
+}
+
+/// Put a secret door in a wall between two bits of floor that are a long
+/// walk apart.
+fn hide_shortcut(start: (i32, i32), map: &mut Map, rng: &mut GameRng) {
+    let (width, height) = map_size(map);
+    let distances = walking_distances(map, start, is_safe);
+    // not next to a door, so no door is left without walls around it
+    let distance = |x: i32, y: i32| {
+        distances[x as usize][y as usize].filter(|_| map[x as usize][y as usize].door.is_none())
+    };
+    let wall = |x: i32, y: i32| is_rock(&map[x as usize][y as usize]);
+
+    let mut shortcuts = vec![];
+    for x in 1 .. width - 1 {
+        for y in 1 .. height - 1 {
+            if !wall(x, y) {
+                continue;
+            }
+            // floor on two opposite sides, and wall on the other two
+            for &(dx, dy) in &[(1, 0), (0, 1)] {
+                if !wall(x + dy, y + dx) || !wall(x - dy, y - dx) {
+                    continue;
+                }
+                if let (Some(a), Some(b)) = (distance(x - dx, y - dy), distance(x + dx, y + dy)) {
+                    if (a - b).abs() >= SHORTCUT_MIN_SAVING {
+                        shortcuts.push((x, y));
+                    }
+                }
+            }
+        }
+    }
+    if !shortcuts.is_empty() {
+        let (x, y) = shortcuts[rng.gen_range(0, shortcuts.len())];
+        map[x as usize][y as usize] = Tile::door(Door::Secret);
+    }
+}
+
+/// Carve a small room out of the rock next to the floor, with a secret door
+/// as the only way in, and leave some treasure in it.
+fn hide_treasure_room(map: &mut Map, objects: &mut Vec<Object>, rng: &mut GameRng) {
+    use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+
+    let (width, height) = map_size(map);
+    for _ in 0 .. TREASURE_ROOM_TRIES {
+        let w = rng.gen_range(4, 7);
+        let h = rng.gen_range(4, 6);
+        let room = Rect::new(rng.gen_range(1, width - w - 1), rng.gen_range(1, height - h - 1), w, h);
+        // solid rock, walls included
+        let solid = (room.x1 .. room.x2 + 1).all(|x| {
+            (room.y1 .. room.y2 + 1).all(|y| is_rock(&map[x as usize][y as usize]))
+        });
+        if !solid {
+            continue;
+        }
+        // a place in the walls with floor outside it
+        let mut entrances = vec![];
+        for x in (room.x1 + 1) .. room.x2 {
+            entrances.push(((x, room.y1), (x, room.y1 - 1)));
+            entrances.push(((x, room.y2), (x, room.y2 + 1)));
+        }
+        for y in (room.y1 + 1) .. room.y2 {
+            entrances.push(((room.x1, y), (room.x1 - 1, y)));
+            entrances.push(((room.x2, y), (room.x2 + 1, y)));
+        }
+        entrances.retain(|&(_, (x, y))| {
+            let tile = &map[x as usize][y as usize];
+            !tile.blocked && !tile.terrain.is_hazard() && tile.door.is_none()
+        });
+        if entrances.is_empty() {
+            continue;
+        }
+
+        create_room(&room, map);
+        let ((x, y), _) = entrances[rng.gen_range(0, entrances.len())];
+        map[x as usize][y as usize] = Tile::door(Door::Secret);
+
+        let treasure_chances = &mut [
+            Weighted { weight: 40, item: Item::Heal },
+            Weighted { weight: 20, item: Item::Lightning },
+            Weighted { weight: 15, item: Item::Confuse },
+            Weighted { weight: 15, item: Item::Fireball },
+            Weighted { weight: 5, item: Item::Sword },
+            Weighted { weight: 5, item: Item::Shield },
+        ];
+        let treasure_choice = WeightedChoice::new(treasure_chances);
+        let mut tiles = room.tiles();
+        for _ in 0 .. rng.gen_range(2, 5) {
+            let (x, y) = tiles.swap_remove(rng.gen_range(0, tiles.len()));
+            objects.push(make_item(treasure_choice.ind_sample(rng), x, y));
+        }
+        return;
+    }
+}
+
+/// How many steps it takes to walk to every tile from `start`, going only
+/// over tiles for which `passable` is true. `None` for the tiles out of reach.
+pub fn walking_distances<F: Fn(&Tile) -> bool>(map: &Map, start: (i32, i32), passable: F) -> Vec<Vec<Option<i32>>> {
+    let (width, height) = map_size(map);
+    let mut distances = vec![vec![None; height as usize]; width as usize];
+    distances[start.0 as usize][start.1 as usize] = Some(0);
+    let mut tiles = vec![start];
+    let mut next = 0;
+    while next < tiles.len() {
+        let (x, y) = tiles[next];
+        next += 1;
+        let distance = distances[x as usize][y as usize].unwrap_or(0) + 1;
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                if distances[x as usize][y as usize].is_none() && passable(&map[x as usize][y as usize]) {
+                    distances[x as usize][y as usize] = Some(distance);
+                    tiles.push((x, y));
+                }
+            }
+        }
+    }
+    distances
+}
+
+/// Where corridors go through the walls of a room: the bare floor tiles in
+/// its walls with wall on either side, so a door fits.
+fn doorways(room: &Rect, map: &Map) -> Vec<(i32, i32)> {
+    let blocked = |x: i32, y: i32| map[x as usize][y as usize].blocked;
+    let floor = |x: i32, y: i32| is_bare_floor(map, (x, y));
+    let mut doorways = vec![];
+    for x in (room.x1 + 1) .. room.x2 {
+        for &y in &[room.y1, room.y2] {
+            if floor(x, y) && blocked(x - 1, y) && blocked(x + 1, y) {
+                doorways.push((x, y));
+            }
+        }
+    }
+    for y in (room.y1 + 1) .. room.y2 {
+        for &x in &[room.x1, room.x2] {
+            if floor(x, y) && blocked(x, y - 1) && blocked(x, y + 1) {
+                doorways.push((x, y));
+            }
+        }
+    }
+    doorways
+}
+
+/// Join two points with an L-shaped tunnel, bending one way or the other.
+pub fn connect((prev_x, prev_y): (i32, i32), (new_x, new_y): (i32, i32), map: &mut Map,
+        rng: &mut GameRng) {
+    if rng.gen() {
+        create_h_tunnel(prev_x, new_x, prev_y, map);
+        create_v_tunnel(prev_y, new_y, new_x, map);
+    } else {
+        create_v_tunnel(prev_y, new_y, prev_x, map);
+        create_h_tunnel(prev_x, new_x, new_y, map);
+    }
+}
+
+fn create_room(room: &Rect, map: &mut Map) {
+    for x in (room.x1 + 1) .. room.x2 {
+        for y in (room.y1 + 1) .. room.y2 {
+            map[x as usize][y as usize] = Tile::empty();
+        }
+    }
+}
+
+fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    for x in cmp::min(x1, x2) .. (cmp::max(x1, x2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    for y in cmp::min(y1, y2) .. (cmp::max(y1, y2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+/// Spawn monsters and items on random tiles of an area, usually a room. The
+/// theme decides which monsters, and which items are more common. No monster
+/// stands on the tiles in `keep_clear`.
+fn place_objects(area: &[(i32, i32)], keep_clear: &[(i32, i32)], map: &mut Map, objects: &mut Vec<Object>,
+        level: u32, theme: Theme, rng: &mut GameRng) {
+    use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+
+    let max_monsters = from_dungeon_level(&[
+        Transition { level: 1, value: 2 },
+        Transition { level: 4, value: 3 },
+        Transition { level: 6, value: 5},
+        ], level);
+
+    let num_monsters = rng.gen_range(0, max_monsters + 1);
+
+    let monster_chances = &mut theme.monster_chances(level);
+    let monster_choice = WeightedChoice::new(monster_chances);
+
+    let monsters_from = objects.len();
+    for _ in 0 .. num_monsters {
+        let (x, y) = area[rng.gen_range(0, area.len())];
+
+        if !is_blocked(x, y, map, objects) && !map[x as usize][y as usize].terrain.is_hazard()
+                && !keep_clear.contains(&(x, y)) {
+            let mut monster = make_monster(monster_choice.ind_sample(rng), x, y);
+            let roll = rng.gen_range(0, 100);
+            if roll < MONSTER_ASLEEP_CHANCE {
+                monster.ai = Some(Ai::Asleep);
+            } else if roll < MONSTER_ASLEEP_CHANCE + MONSTER_WANDERING_CHANCE {
+                monster.ai = Some(Ai::Wandering { x: x, y: y });
+            }
+            objects.push(monster);
+        }
+    }
+    if rng.gen_range(0, 100) < PACK_CHANCE {
+        form_pack(&mut objects[monsters_from ..]);
+    }
+
+    let max_items = from_dungeon_level(&[
+        Transition { level: 1, value: 1},
+        Transition { level: 4, value: 2},
+        ], level);
+
+    let item_chances = &mut [
+        Weighted { weight: 70, item: Item::Heal },
+        Weighted {weight: from_dungeon_level(&[Transition{level: 4, value: 25}], level),
+                  item: Item::Lightning},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 6, value: 25}], level),
+                  item: Item::Fireball},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 2, value: 10}], level),
+                  item: Item::Confuse},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 4, value: 5}], level),
+                  item: Item::Sword},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 8, value: 15}], level),
+                  item: Item::Shield},
+    ];
+    for chance in item_chances.iter_mut() {
+        chance.weight = chance.weight * theme.loot_bias(chance.item) / 100;
+    }
+
+    let item_choice = WeightedChoice::new(item_chances);
+
+    for _ in 0 .. rng.gen_range(0, max_items + 1) {
+        let (x, y) = area[rng.gen_range(0, area.len())];
+
+        if !is_blocked(x, y, map, objects) && !map[x as usize][y as usize].terrain.is_hazard() {
+            objects.push(make_item(item_choice.ind_sample(rng), x, y));
+        }
+    }
+
+    // chance of a trap, in percent
+    let trap_chance = from_dungeon_level(&[
+        Transition { level: 1, value: 5 },
+        Transition { level: 3, value: 15 },
+        Transition { level: 6, value: 25 },
+        ], level);
+
+    let trap_chances = &mut [
+        Weighted { weight: 40, item: TrapKind::Dart },
+        Weighted {weight: from_dungeon_level(&[Transition{level: 2, value: 20}], level),
+                  item: TrapKind::Alarm},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 3, value: 15}], level),
+                  item: TrapKind::Teleport},
+        Weighted {weight: from_dungeon_level(&[Transition{level: 4, value: 15}], level),
+                  item: TrapKind::Pit},
+    ];
+
+    let trap_choice = WeightedChoice::new(trap_chances);
+
+    if rng.gen_range(0, 100) < trap_chance {
+        let (x, y) = area[rng.gen_range(0, area.len())];
+
+        if is_bare_floor(map, (x, y)) {
+            map[x as usize][y as usize].trap = Some(Trap { kind: trap_choice.ind_sample(rng), known: false });
+        }
+    }
+}
+
+/// Make a pack of the monsters that have a morale, led by the toughest of
+/// them. One on its own is no pack.
+fn form_pack(monsters: &mut [Object]) {
+    let members: Vec<usize> = (0 .. monsters.len()).filter(|&i| monsters[i].morale.is_some()).collect();
+    if members.len() < 2 {
+        return;
+    }
+    let leader = members.iter().cloned()
+        .max_by_key(|&i| monsters[i].fighter.map_or(0, |f| f.base_max_hp))
+        .unwrap();
+    let pack = monsters[leader].pos();
+    for i in members {
+        if let Some(morale) = monsters[i].morale.as_mut() {
+            morale.pack = Some(pack);
+            morale.leader = i == leader;
+        }
+    }
+}
+
+/// A new monster of a kind, like "orc" or "troll".
+pub fn make_monster(kind: &str, x: i32, y: i32) -> Object {
+    let mut monster = match kind {
+        "orc" => {
+            let mut orc = Object::new(x, y, 'o', "orc", colors::DESATURATED_GREEN, true);
+            orc.fighter = Some(Fighter { base_max_hp: 20, hp: 20, base_defense: 0, base_power: 4,
+                on_death: DeathCallback::Monster, xp: 35 });
+            orc.ai = Some(Ai::Basic);
+            orc.morale = Some(Morale { flee_below: 20, pack: None, leader: false });
+            orc.senses = Some(Senses { sight_radius: 8, hearing_radius: 2, perception: 60 });
+            orc
+        },
+        "troll" => {
+            let mut troll = Object::new(x, y, 'T', "troll", colors::DARKER_GREEN, true);
+            troll.fighter = Some(Fighter { base_max_hp: 30, hp: 30, base_defense: 2, base_power: 8,
+                on_death: DeathCallback::Monster, xp: 100 });
+            troll.ai = Some(Ai::Basic);
+            troll.morale = Some(Morale { flee_below: 0, pack: None, leader: false });
+            troll.senses = Some(Senses { sight_radius: 6, hearing_radius: 2, perception: 40 });
+            troll
+        },
+        "goblin" => {
+            let mut goblin = Object::new(x, y, 'g', "goblin", colors::LIGHT_YELLOW, true);
+            goblin.fighter = Some(Fighter { base_max_hp: 12, hp: 12, base_defense: 0, base_power: 3,
+                on_death: DeathCallback::Monster, xp: 20 });
+            goblin.ai = Some(Ai::Basic);
+            goblin.morale = Some(Morale { flee_below: 40, pack: None, leader: false });
+            goblin.senses = Some(Senses { sight_radius: 10, hearing_radius: 3, perception: 80 });
+            goblin
+        },
+        "skeleton" => {
+            let mut skeleton = Object::new(x, y, 's', "skeleton", colors::WHITE, true);
+            skeleton.fighter = Some(Fighter { base_max_hp: 18, hp: 18, base_defense: 2, base_power: 5,
+                on_death: DeathCallback::Monster, xp: 50 });
+            skeleton.ai = Some(Ai::Basic);
+            skeleton.senses = Some(Senses { sight_radius: 6, hearing_radius: 1, perception: 50 });
+            skeleton
+        },
+        "zombie" => {
+            let mut zombie = Object::new(x, y, 'z', "zombie", colors::LIGHT_GREEN, true);
+            zombie.fighter = Some(Fighter { base_max_hp: 35, hp: 35, base_defense: 0, base_power: 6,
+                on_death: DeathCallback::Monster, xp: 60 });
+            zombie.ai = Some(Ai::Basic);
+            zombie.senses = Some(Senses { sight_radius: 4, hearing_radius: 1, perception: 30 });
+            zombie
+        },
+        "lizardman" => {
+            let mut lizardman = Object::new(x, y, 'l', "lizardman", colors::LIGHT_CYAN, true);
+            lizardman.fighter = Some(Fighter { base_max_hp: 24, hp: 24, base_defense: 1, base_power: 6,
+                on_death: DeathCallback::Monster, xp: 60 });
+            lizardman.ai = Some(Ai::Basic);
+            lizardman.morale = Some(Morale { flee_below: 25, pack: None, leader: false });
+            lizardman.senses = Some(Senses { sight_radius: 8, hearing_radius: 3, perception: 60 });
+            lizardman
+        },
+        "orc warlord" => {
+            let mut warlord = Object::new(x, y, 'O', "orc warlord", colors::LIGHT_RED, true);
+            warlord.fighter = Some(Fighter { base_max_hp: 80, hp: 80, base_defense: 4, base_power: 12,
+                on_death: DeathCallback::Monster, xp: 500 });
+            warlord.ai = Some(Ai::Basic);
+            warlord.morale = Some(Morale { flee_below: 0, pack: None, leader: false });
+            warlord.senses = Some(Senses { sight_radius: 10, hearing_radius: 3, perception: 90 });
+            warlord
+        },
+        "goblin archer" => {
+            // far-sighted, and keeps its distance
+            let mut archer = Object::new(x, y, 'g', "goblin archer", colors::ORANGE, true);
+            archer.fighter = Some(Fighter { base_max_hp: 10, hp: 10, base_defense: 0, base_power: 2,
+                on_death: DeathCallback::Monster, xp: 40 });
+            archer.ai = Some(Ai::Basic);
+            archer.morale = Some(Morale { flee_below: 40, pack: None, leader: false });
+            archer.senses = Some(Senses { sight_radius: 12, hearing_radius: 2, perception: 80 });
+            archer.ranged = Some(Ranged { projectile: Projectile::Arrow, range: 8, power: 5, recharge: 2,
+                recharging: 0 });
+            archer
+        },
+        "kobold shaman" => {
+            let mut shaman = Object::new(x, y, 'k', "kobold shaman", colors::SKY, true);
+            shaman.fighter = Some(Fighter { base_max_hp: 12, hp: 12, base_defense: 0, base_power: 2,
+                on_death: DeathCallback::Monster, xp: 60 });
+            shaman.ai = Some(Ai::Basic);
+            shaman.morale = Some(Morale { flee_below: 30, pack: None, leader: false });
+            shaman.senses = Some(Senses { sight_radius: 8, hearing_radius: 2, perception: 60 });
+            shaman.ranged = Some(Ranged { projectile: Projectile::Lightning, range: 6, power: 8, recharge: 4,
+                recharging: 0 });
+            shaman
+        },
+        "cave crawler" => {
+            // blind, but it hears the player coming from afar
+            let mut crawler = Object::new(x, y, 'c', "cave crawler", colors::LIGHT_VIOLET, true);
+            crawler.fighter = Some(Fighter { base_max_hp: 16, hp: 16, base_defense: 1, base_power: 5,
+                on_death: DeathCallback::Monster, xp: 45 });
+            crawler.ai = Some(Ai::Basic);
+            crawler.senses = Some(Senses { sight_radius: 0, hearing_radius: 6, perception: 100 });
+            crawler
+        },
+        _ => panic!("unknown monster '{}'", kind),
+    };
+    monster.alive = true;
+    monster
+}
+
+/// A new item lying on the floor.
+pub fn make_item(item: Item, x: i32, y: i32) -> Object {
+    match item {
+        Item::Heal => {
+            let mut object = Object::new(x, y, '!', "healing potion", colors::VIOLET, false);
+            object.always_visible = true;
+            object.item = Some(Item::Heal);
+            object
+        },
+        Item::Lightning => {
+            let mut object = Object::new(x, y, '#', "scroll of lightning bolt",
+                                        colors::DARK_GREEN, false);
+            object.always_visible = true;
+            object.item = Some(Item::Lightning);
+            object
+        },
+        Item::Fireball => {
+            let mut object = Object::new(x, y, '#', "scroll of fireball", colors::LIGHT_YELLOW,
+                false);
+            object.always_visible = true;
+            object.item = Some(Item::Fireball);
+            object
+        },
+        Item::Confuse => {
+            let mut object = Object::new(x, y, '#', "scroll of confusion",
+                                            colors::LIGHT_YELLOW, false);
+            object.always_visible = true;
+            object.item = Some(Item::Confuse);
+            object
+        },
+        Item::Sword => {
+            let mut object = Object::new(x, y, '/', "sword", colors::SKY, false);
+            object.item = Some(Item::Sword);
+            object.equipment = Some(Equipment { equipped: false, slot: Slot::RightHand, power_bonus: 3, defense_bonus: 0, max_hp_bonus: 0 });
+            object
+        },
+        Item::Shield => {
+            let mut object = Object::new(x, y, '[', "shield", colors::DARKER_ORANGE,
+                false);
+            object.item = Some(Item::Shield);
+            object.equipment = Some(Equipment { equipped: false, slot: Slot::LeftHand,
+                max_hp_bonus: 0, defense_bonus: 1, power_bonus: 0 });
+            object
+        }
+        Item::Artifact => {
+            let mut object = Object::new(x, y, '"', "Amulet of the Ancient Kings", colors::YELLOW,
+                                         false);
+            object.always_visible = true;
+            object.item = Some(Item::Artifact);
+            object
+        }
+        Item::Key => {
+            let mut object = Object::new(x, y, '-', "key", colors::YELLOW, false);
+            object.always_visible = true;
+            object.item = Some(Item::Key);
+            object
+        }
+    }
+}
+
+/// Put the Amulet of the Ancient Kings where the down stairs of a freshly
+/// made level are: it lies at the bottom of the dungeon.
+pub fn place_artifact(objects: &mut Vec<Object>) {
+    if let Some(stairs) = objects.iter().position(|object| object.name == "down stairs") {
+        let (x, y) = objects[stairs].pos();
+        objects[stairs] = make_item(Item::Artifact, x, y);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::path::Path;
+    use std::thread;
+
+    use super::*;
+    use vaults::load_vaults;
+    use FINAL_DEPTH;
+
+    /// Generate a level, and check that all its floor and everything on it
+    /// can be reached from the up stairs.
+    fn check_level(seed: u64, level: u32, generator: Generator, theme: Theme, vaults: &[Vault]) {
+        let mut rng = GameRng::new(seed);
+        let mut objects = vec![Object::new(0, 0, '@', "player", colors::WHITE, true)];
+        let map = make_map_with(generator, &mut objects, level, theme, (MAP_WIDTH, MAP_HEIGHT), vaults, &mut rng);
+        if level == FINAL_DEPTH {
+            place_artifact(&mut objects);
+        }
+
+        let start = objects.iter().find(|object| object.name == "up stairs").unwrap().pos();
+        let reached: HashSet<_> = flood_fill(&map, start).into_iter().collect();
+        let (width, height) = map_size(&map);
+        for x in 0 .. width {
+            for y in 0 .. height {
+                assert!(map[x as usize][y as usize].blocked || reached.contains(&(x, y)),
+                        "seed {}, level {}, {:?}: floor at {:?} is cut off", seed, level, generator, (x, y));
+            }
+        }
+        let way_down = if level == FINAL_DEPTH { "Amulet of the Ancient Kings" } else { "down stairs" };
+        assert!(objects.iter().any(|object| object.name == way_down));
+        let stairs: Vec<_> = objects.iter()
+            .filter(|object| object.name.ends_with(" stairs"))
+            .map(Object::pos)
+            .collect();
+        for object in objects[PLAYER + 1 ..].iter().filter(|object| object.blocks) {
+            assert!(!stairs.contains(&object.pos()), "seed {}, level {}, {:?}: {} at {:?} is on the stairs",
+                    seed, level, generator, object.name, object.pos());
+        }
+        for object in &objects {
+            let (x, y) = object.pos();
+            assert!(reached.contains(&(x, y)), "seed {}, level {}, {:?}: {} at {:?} is cut off",
+                    seed, level, generator, object.name, (x, y));
+            assert!(!map[x as usize][y as usize].terrain.is_hazard(), "seed {}, level {}, {:?}: {} at {:?} is in {}",
+                    seed, level, generator, object.name, (x, y), map[x as usize][y as usize].terrain.name());
+        }
+    }
+
+    #[test]
+    fn all_of_every_level_can_be_reached() {
+        let vaults: Vec<Vault> = load_vaults(&Path::new(env!("CARGO_MANIFEST_DIR")).join("vaults")).unwrap()
+            .into_iter()
+            .map(|mut vault| { vault.rarity = 1; vault })
+            .collect();
+        assert!(!vaults.is_empty());
+        let generators = [(Generator::Rooms, Theme::Dungeon), (Generator::Bsp, Theme::FloodedHalls),
+                          (Generator::Caves, Theme::Mine), (Generator::Lair, Theme::Lair)];
+        // a generator to a thread, as that's a lot of levels
+        let checks: Vec<_> = generators.iter().map(|&(generator, theme)| {
+            let vaults = vaults.clone();
+            thread::spawn(move || {
+                for seed in 0 .. 2000 {
+                    let level = 1 + seed as u32 % FINAL_DEPTH;
+                    let vaults = if seed % 2 == 0 { &vaults[..] } else { &[] };
+                    check_level(seed, level, generator, theme, vaults);
+                }
+            })
+        }).collect();
+        for check in checks {
+            assert!(check.join().is_ok());
+        }
+    }
+
+    #[test]
+    fn caves_are_shared_out_in_local_areas() {
+        for seed in 0..50 {
+            let mut rng = GameRng::new(seed);
+            let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+            let layout = make_caves(&mut map, &mut rng);
+            let floor = flood_fill(&map, layout.up_stairs);
+            assert_eq!(layout.areas.len(), floor.len() / CAVE_TILES_PER_AREA);
+
+            let mut claimed = HashSet::new();
+            for area in &layout.areas {
+                assert!(!area.is_empty());
+                for &tile in area {
+                    assert!(claimed.insert(tile), "seed {}: {:?} is in two areas", seed, tile);
+                }
+                // all of an area can be walked without leaving it
+                let tiles: HashSet<_> = area.iter().cloned().collect();
+                let mut reached = HashSet::new();
+                let mut open = vec![area[0]];
+                while let Some((x, y)) = open.pop() {
+                    for dx in -1 .. 2 {
+                        for dy in -1 .. 2 {
+                            let next = (x + dx, y + dy);
+                            if tiles.contains(&next) && reached.insert(next) {
+                                open.push(next);
+                            }
+                        }
+                    }
+                }
+                assert_eq!(reached.len(), area.len(), "seed {}: an area is split", seed);
+            }
+            if !layout.areas.is_empty() {
+                assert_eq!(claimed.len(), floor.len());
+            }
+        }
+    }
+}
@@ -0,0 +1,419 @@
+use colors::{self, Color};
+use game::Game;
+use CONFUSE_RANGE;
+
+pub type Messages = Vec<(String, Color)>;
+
+pub trait MessageLog {
+    fn add<T: Into<String>>(&mut self, message: T, color: Color);
+}
+
+impl MessageLog for Vec<(String, Color)> {
+    fn add<T: Into<String>>(&mut self, message: T, color: Color) {
+        self.push((message.into(), color));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Fighter {
+    pub base_max_hp: i32,
+    pub hp: i32,
+    pub base_defense: i32,
+    pub base_power: i32,
+    pub on_death: DeathCallback,
+    pub xp: i32,
+}
+
+/// How a monster notices the player.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Senses {
+    /// How far it sees; 0 if it's blind.
+    pub sight_radius: i32,
+    /// How close the player must be for it to hear them, walls or not.
+    pub hearing_radius: i32,
+    /// How keen it is: how likely it notices the player in sight, every
+    /// turn, in percent. Asleep, it's less likely to notice them at all.
+    pub perception: u32,
+}
+
+/// How long a monster keeps fighting.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Morale {
+    /// Below how many percent of its hit points it runs away; 0 if it never
+    /// does.
+    pub flee_below: i32,
+    /// The pack it belongs to, known by where its leader started out, and
+    /// whether it leads it. When the leader dies, the pack breaks and runs.
+    pub pack: Option<(i32, i32)>,
+    pub leader: bool,
+}
+
+/// What a monster shoots or casts at the player from afar.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Projectile {
+    /// Stopped by armor, as blows are.
+    Arrow,
+    /// Goes right through armor.
+    Lightning,
+}
+
+impl Projectile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Projectile::Arrow => "an arrow",
+            Projectile::Lightning => "a bolt of lightning",
+        }
+    }
+
+    pub fn verb(self) -> &'static str {
+        match self {
+            Projectile::Arrow => "shoots",
+            Projectile::Lightning => "casts",
+        }
+    }
+}
+
+/// A monster's attack from afar.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct Ranged {
+    pub projectile: Projectile,
+    pub range: i32,
+    pub power: i32,
+    /// How many turns it takes to shoot again, and how many are left.
+    pub recharge: i32,
+    pub recharging: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum DeathCallback {
+    Player,
+    Monster,
+}
+
+impl DeathCallback {
+    /// `by_player` is whether the player killed them, rather than a trap or
+    /// the terrain.
+    fn callback(self, object: &mut Object, game: &mut Game, by_player: bool) {
+        use self::DeathCallback::*;
+        let callback: fn (&mut Object, &mut Game, bool) = match self {
+            Player => player_death,
+            Monster => monster_death,
+        };
+        callback(object, game, by_player);
+    }
+}
+
+fn player_death(player: &mut Object, game: &mut Game, _by_player: bool) {
+    game.log.add("You died!", colors::RED);
+
+    player.char = '%';
+    player.color = colors::DARK_RED;
+}
+
+fn monster_death(monster: &mut Object, game: &mut Game, by_player: bool) {
+    if by_player {
+        game.kills += 1;
+        game.log.add(format!("{} is dead! You gain {} experience points.",
+            monster.name, monster.fighter.unwrap().xp), colors::RED);
+    } else {
+        game.log.add(format!("{} is dead!", monster.name), colors::RED);
+    }
+    monster.char = '%';
+    monster.color = colors::DARK_RED;
+    monster.blocks = false;
+    monster.fighter = None;
+    monster.ai = None;
+    monster.name = format!("remains of {}", monster.name);
+}
+
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub enum Ai {
+    /// Awake and standing guard until it notices the player.
+    Basic,
+    /// Sleeping until it notices the player, which is harder than awake, or
+    /// is hurt.
+    Asleep,
+    /// Roaming the level, to a place picked at random and on to the next.
+    Wandering { x: i32, y: i32 },
+    /// After the player, heading for where they were last seen.
+    Hunting { x: i32, y: i32 },
+    /// Lost track of the player and looking around a place for them, until it
+    /// gives up and wanders off.
+    Searching { x: i32, y: i32, num_turns: i32 },
+    Confused { previous_ai: Box<Ai>, num_turns: i32 },
+    /// Running away from the player for a while, until it has healed. It
+    /// fights back when cornered.
+    Fleeing { num_turns: i32 },
+    /// Heading for a noise, such as an alarm, until it gives up or notices
+    /// the player.
+    Alerted { x: i32, y: i32, num_turns: i32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Item {
+    Heal,
+    Lightning,
+    Confuse,
+    Fireball,
+    Sword,
+    Shield,
+    /// The Amulet of the Ancient Kings, which wins the game when carried out
+    /// of the dungeon.
+    Artifact,
+    /// Opens a locked door, and is used up doing it.
+    Key,
+}
+
+impl Item {
+    pub fn targeting(self) -> Targeting {
+        use Item::*;
+        match self {
+            Confuse => Targeting::Monster { max_range: Some(CONFUSE_RANGE as f32) },
+            Fireball => Targeting::Tile { max_range: None },
+            Heal | Lightning | Sword | Shield | Artifact | Key => Targeting::None,
+        }
+    }
+}
+
+/// What a front-end has to ask the player for before an item can be used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Targeting {
+    None,
+    Tile { max_range: Option<f32> },
+    Monster { max_range: Option<f32> },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct Equipment {
+    pub slot: Slot,
+    pub equipped: bool,
+    pub power_bonus: i32,
+    pub defense_bonus: i32,
+    pub max_hp_bonus: i32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub enum Slot {
+    LeftHand,
+    RightHand,
+    Head,
+}
+
+impl ::std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        match *self {
+            Slot::LeftHand => write!(f, "left hand"),
+            Slot::RightHand => write!(f, "right hand"),
+            Slot::Head => write!(f, "head")
+        }
+    }
+}
+
+/// This is a generic object: the player, a monster, an item, the stairs...
+/// It's always represented by a character on screen.
+#[derive(Debug, RustcEncodable, RustcDecodable)]
+pub struct Object {
+    pub x: i32,
+    pub y: i32,
+    pub char: char,
+    pub color: Color,
+    pub name: String,
+    pub blocks: bool,
+    pub alive: bool,
+    pub fighter: Option<Fighter>,
+    pub ai: Option<Ai>,
+    pub senses: Option<Senses>,
+    pub ranged: Option<Ranged>,
+    pub morale: Option<Morale>,
+    pub item: Option<Item>,
+    pub always_visible: bool,
+    pub level: i32,
+    pub equipment: Option<Equipment>,
+}
+
+impl Object {
+    pub fn new(x: i32, y: i32, char: char, name: &str, color: Color, blocks: bool) -> Self {
+        Object {
+            x: x,
+            y: y,
+            char: char,
+            color: color,
+            name: name.into(),
+            blocks: blocks,
+            alive: false,
+            fighter: None,
+            ai: None,
+            senses: None,
+            ranged: None,
+            morale: None,
+            item: None,
+            always_visible: false,
+            level: 1,
+            equipment: None,
+        }
+    }
+
+    pub fn pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    pub fn set_pos(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn distance_to(&self, other: &Object) -> f32 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+    }
+
+    pub fn take_damage(&mut self, damage: i32, game: &mut Game) -> Option<i32> {
+        self.lose_hp(damage, game, true)
+    }
+
+    /// Take damage from the dungeon itself, like a trap, rather than from
+    /// someone. A monster killed this way is no kill of the player's, and
+    /// gives no experience.
+    pub fn take_damage_from_dungeon(&mut self, damage: i32, game: &mut Game) {
+        self.lose_hp(damage, game, false);
+    }
+
+    fn lose_hp(&mut self, damage: i32, game: &mut Game, by_player: bool) -> Option<i32> {
+        if let Some(fighter) = self.fighter.as_mut() {
+            if damage > 0 {
+                fighter.hp -= damage;
+            }
+        }
+        if let Some(fighter) = self.fighter {
+            if fighter.hp <= 0 {
+                self.alive = false;
+                fighter.on_death.callback(self, game, by_player);
+                return Some(fighter.xp);
+            }
+        }
+        None
+    }
+
+    pub fn attack(&mut self, target: &mut Object, game: &mut Game) {
+        let damage = self.power(game) - target.defense(game);
+
+        if damage > 0 {
+            game.log.add(
+                format!("{} attacks {} for {} hit points.", self.name, target.name, damage),
+                colors::RED);
+            if let Some(xp) = target.take_damage(damage, game) {
+                self.fighter.as_mut().unwrap().xp += xp;
+            }
+        } else {
+            game.log.add(
+                format!("{} attacks {} but it has no effect!", self.name, target.name),
+                colors::RED);
+        }
+    }
+
+    pub fn heal(&mut self, amount: i32, game: &Game) {
+        let max_hp = self.max_hp(game);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp += amount;
+            if fighter.hp > max_hp {
+                fighter.hp = max_hp;
+            }
+        }
+    }
+
+    pub fn distance(&self, x: i32, y: i32) -> f32 {
+        (((x - self.x).pow(2) + (y - self.y).pow(2)) as f32).sqrt()
+    }
+
+    pub fn equip(&mut self, log: &mut Vec<(String, Color)>) {
+        if self.item.is_none() {
+            log.add(format!("Can't equip {:?} because it's not an Item.", self),
+                colors::RED);
+            return
+        };
+        if let Some(ref mut equipment) = self.equipment {
+            if !equipment.equipped {
+                equipment.equipped = true;
+                log.add(format!("Equippe {} on {}", self.name, equipment.slot),
+                    colors::LIGHT_GREEN);
+            }
+        } else {
+            log.add(format!("Can't equip {:?} because it's not an Equipment.",
+                self), colors::RED);
+        }
+    }
+
+    pub fn dequip(&mut self, log: &mut Vec<(String, Color)>) {
+        if self.item.is_none() {
+            log.add(format!("Can't dequip {:?} because it's not an Item.", self),
+                colors::RED);
+            return
+        };
+        if let Some(ref mut equipment) = self.equipment {
+            if equipment.equipped {
+                equipment.equipped = false;
+                log.add(format!("Dequipped {} from {}.", self.name, equipment.slot),
+                    colors::LIGHT_YELLOW);
+            }
+        } else {
+            log.add(format!("Can't dequip {:?} because it's not an Equipment.", self),
+                colors::RED);
+        }
+    }
+
+    pub fn power(&self, game: &Game) -> i32 {
+        let base_power = self.fighter.map_or(0, |f| f.base_power);
+        let bonus = self.get_all_equipped(game).iter().fold(0, |sum, e| sum + e.power_bonus);
+
+        base_power + bonus
+    }
+
+    pub fn defense(&self, game: &Game) -> i32 {
+        let base_defense = self.fighter.map_or(0, |f| f.base_defense);
+        let bonus = self.get_all_equipped(game).iter().fold(0, |sum, e| sum + e.defense_bonus);
+        base_defense + bonus
+    }
+
+    pub fn max_hp(&self, game: &Game) -> i32 {
+        let base_max_hp = self.fighter.map_or(0, |f| f.base_max_hp);
+        let bonus = self.get_all_equipped(game).iter().fold(0, |sum, e| sum + e.max_hp_bonus);
+        base_max_hp + bonus
+    }
+
+    pub fn get_all_equipped(&self, game: &Game) -> Vec<Equipment> {
+        if self.name == "player" {
+            game.inventory
+                .iter()
+                .filter(|item| {
+                    item.equipment.map_or(false, |e| e.equipped)
+                })
+                .map(|item| item.equipment.unwrap())
+                .collect()
+        } else {
+            vec![]
+        }
+    }
+}
+
+pub fn get_equipped_in_slot(slot: Slot, inventory: &[Object]) -> Option<usize> {
+    for (inventory_id, item) in inventory.iter().enumerate() {
+        if item.equipment.as_ref().map_or(false, |e| e.equipped && e.slot == slot) {
+            return Some(inventory_id)
+        }
+    }
+    None
+}
+
+/// Borrow two distinct elements of a slice mutably at the same time.
+pub fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+    use std::cmp;
+    assert!(first_index != second_index);
+    let split_at_index = cmp::max(first_index, second_index);
+    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+    if first_index < second_index {
+        (&mut first_slice[first_index], &mut second_slice[0])
+    } else {
+        (&mut second_slice[0], &mut first_slice[second_index])
+    }
+}
@@ -0,0 +1,315 @@
+//! Finding the way across a level with A*, for monsters going after the
+//! player, and away from them with a safety map, for those running off.
+
+use std::cmp::{self, Ordering, Reverse};
+use std::collections::BinaryHeap;
+
+use map::{map_size, walking_distances, Door, Map, Tile};
+use object::Object;
+use {FLEE_SAFETY_FACTOR, PATH_MAX_TILES, PATH_OBSTACLE_COST};
+
+/// A tile waiting to be looked at. The heap gives the lowest estimate of the
+/// whole way first, and of those the one furthest along.
+#[derive(PartialEq, Eq)]
+struct Step {
+    estimate: u32,
+    cost: u32,
+    pos: (i32, i32),
+}
+
+impl Ord for Step {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+            .then_with(|| self.cost.cmp(&other.cost))
+            .then_with(|| self.pos.cmp(&other.pos))
+    }
+}
+
+impl PartialOrd for Step {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// How many turns it takes to step onto a tile, if it can be done at all.
+/// Closed doors take a turn to open first; locked and secret ones, lava and
+/// chasms are as good as walls.
+fn step_cost(tile: &Tile) -> Option<u32> {
+    if tile.terrain.is_hazard() {
+        return None;
+    }
+    match tile.door {
+        Some(Door::Closed) => Some(tile.terrain.movement_cost() + 1),
+        Some(Door::Locked) | Some(Door::Secret) => None,
+        _ if tile.blocked => None,
+        _ => Some(tile.terrain.movement_cost()),
+    }
+}
+
+/// The quickest way from `from` to `to`: the tiles to step on, `to`
+/// included. Blocking objects are only in the way for now, so the way may go
+/// through them, but goes around if that isn't much longer. `None` if there
+/// is no way, or if it's too far to look for.
+pub fn find_path(map: &Map, objects: &[Object], from: (i32, i32), to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    let (width, height) = map_size(map);
+    let index = |(x, y): (i32, i32)| (x * height + y) as usize;
+    // moves can be diagonal, so this never overestimates
+    let estimate = |(x, y): (i32, i32)| cmp::max((x - to.0).abs(), (y - to.1).abs()) as u32;
+
+    let mut occupied = vec![false; (width * height) as usize];
+    for object in objects.iter().filter(|object| object.blocks) {
+        occupied[index(object.pos())] = true;
+    }
+    let mut costs = vec![u32::MAX; (width * height) as usize];
+    let mut came_from = vec![None; (width * height) as usize];
+    let mut open = BinaryHeap::new();
+    costs[index(from)] = 0;
+    open.push(Step { estimate: estimate(from), cost: 0, pos: from });
+
+    let mut looked_at = 0;
+    while let Some(Step { cost, pos, .. }) = open.pop() {
+        if pos == to {
+            let mut path = vec![to];
+            let mut pos = to;
+            while let Some(previous) = came_from[index(pos)] {
+                if previous == from {
+                    break;
+                }
+                path.push(previous);
+                pos = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        // a cheaper way here was found since this one was queued
+        if cost > costs[index(pos)] {
+            continue;
+        }
+        looked_at += 1;
+        if looked_at > PATH_MAX_TILES {
+            return None;
+        }
+
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let next = (pos.0 + dx, pos.1 + dy);
+                if (dx, dy) == (0, 0) || next.0 < 0 || next.1 < 0 || next.0 >= width || next.1 >= height {
+                    continue;
+                }
+                let mut next_cost = match step_cost(&map[next.0 as usize][next.1 as usize]) {
+                    Some(step) => cost + step,
+                    None => continue,
+                };
+                if occupied[index(next)] && next != to {
+                    next_cost += PATH_OBSTACLE_COST;
+                }
+                if next_cost < costs[index(next)] {
+                    costs[index(next)] = next_cost;
+                    came_from[index(next)] = Some(pos);
+                    open.push(Step { estimate: next_cost + estimate(next), cost: next_cost, pos: next });
+                }
+            }
+        }
+    }
+    None
+}
+
+/// How safe every tile is from a threat, for monsters running away from it:
+/// the lower, the safer. Going by the distance from it alone leads them into
+/// dead ends, so the distances are scaled up and smoothed out, which draws
+/// them past the threat to open ground farther off. `None` for the tiles
+/// they can't get to.
+pub fn safety_map(map: &Map, threat: (i32, i32)) -> Vec<Vec<Option<i32>>> {
+    let mut safety = walking_distances(map, threat, |tile| step_cost(tile).is_some());
+    let mut open = BinaryHeap::new();
+    for (x, column) in safety.iter_mut().enumerate() {
+        for (y, value) in column.iter_mut().enumerate() {
+            if let Some(distance) = *value {
+                *value = Some(-distance * FLEE_SAFETY_FACTOR / 100);
+                open.push(Reverse((-distance * FLEE_SAFETY_FACTOR / 100, (x as i32, y as i32))));
+            }
+        }
+    }
+    // a tile is never less safe than a step away from a safer one
+    let (width, height) = map_size(map);
+    while let Some(Reverse((value, (x, y)))) = open.pop() {
+        if safety[x as usize][y as usize] != Some(value) {
+            continue;
+        }
+        for dx in -1 .. 2 {
+            for dy in -1 .. 2 {
+                let (x, y) = (x + dx, y + dy);
+                if x < 0 || y < 0 || x >= width || y >= height {
+                    continue;
+                }
+                match safety[x as usize][y as usize] {
+                    Some(other) if other > value + 1 => {
+                        safety[x as usize][y as usize] = Some(value + 1);
+                        open.push(Reverse((value + 1, (x, y))));
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+    safety
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use colors;
+    use map::drawn_map as map;
+
+    fn orc(x: i32, y: i32) -> Object {
+        Object::new(x, y, 'o', "orc", colors::WHITE, true)
+    }
+
+    /// Check that a path is a walk of single steps from `from` to `to`.
+    fn assert_walk(path: &[(i32, i32)], from: (i32, i32), to: (i32, i32)) {
+        let mut pos = from;
+        for &next in path {
+            assert!((next.0 - pos.0).abs() <= 1 && (next.1 - pos.1).abs() <= 1 && next != pos,
+                    "{:?} to {:?} is no step", pos, next);
+            pos = next;
+        }
+        assert_eq!(pos, to);
+    }
+
+    #[test]
+    fn paths_go_around_walls() {
+        let map = map(&[
+            "#######",
+            "#..#..#",
+            "#..#..#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&map, &[], (1, 1), (5, 1)).unwrap();
+        assert_walk(&path, (1, 1), (5, 1));
+        assert_eq!(path.len(), 4);
+        assert!(path.iter().all(|&(x, y)| !map[x as usize][y as usize].blocked));
+    }
+
+    #[test]
+    fn paths_go_around_lava_and_chasms() {
+        let map = map(&[
+            "#######",
+            "#..=..#",
+            "#..:..#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&map, &[], (1, 1), (5, 1)).unwrap();
+        assert_walk(&path, (1, 1), (5, 1));
+        assert!(path.contains(&(3, 3)));
+        assert!(path.iter().all(|&(x, y)| !map[x as usize][y as usize].terrain.is_hazard()));
+    }
+
+    #[test]
+    fn paths_go_through_closed_doors_but_not_locked_ones() {
+        let open = map(&["#####", "#.+.#", "#####"]);
+        assert_eq!(find_path(&open, &[], (1, 1), (3, 1)), Some(vec![(2, 1), (3, 1)]));
+        let locked = map(&["#####", "#.x.#", "#####"]);
+        assert_eq!(find_path(&locked, &[], (1, 1), (3, 1)), None);
+    }
+
+    #[test]
+    fn paths_go_around_others_if_they_can() {
+        let map = map(&[
+            "#######",
+            "#.....#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&map, &[orc(3, 1)], (1, 1), (5, 1)).unwrap();
+        assert_walk(&path, (1, 1), (5, 1));
+        assert_eq!(path.len(), 4);
+        assert!(!path.contains(&(3, 1)));
+    }
+
+    #[test]
+    fn paths_go_through_others_if_they_must() {
+        let corridor = map(&["#######", "#.....#", "#######"]);
+        let path = find_path(&corridor, &[orc(3, 1)], (1, 1), (5, 1)).unwrap();
+        assert_eq!(path, vec![(2, 1), (3, 1), (4, 1), (5, 1)]);
+
+        // the long way round is only taken when it costs less than going through
+        let long_way = map(&[
+            "#######",
+            "#.....#",
+            "#.###.#",
+            "#.###.#",
+            "#.###.#",
+            "#.###.#",
+            "#.###.#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&long_way, &[orc(3, 1)], (1, 1), (5, 1)).unwrap();
+        assert_eq!(path, vec![(2, 1), (3, 1), (4, 1), (5, 1)]);
+        let detour = find_path(&long_way, &[orc(3, 1), orc(4, 1)], (1, 1), (5, 1)).unwrap();
+        assert_walk(&detour, (1, 1), (5, 1));
+        assert!(!detour.contains(&(3, 1)) && !detour.contains(&(4, 1)));
+    }
+
+    #[test]
+    fn the_path_to_where_you_are_is_that_place() {
+        let map = map(&["###", "#.#", "###"]);
+        assert_eq!(find_path(&map, &[], (1, 1), (1, 1)), Some(vec![(1, 1)]));
+    }
+
+    #[test]
+    fn there_is_no_path_out_of_a_closed_room() {
+        let map = map(&[
+            "#######",
+            "#..#..#",
+            "#..#..#",
+            "#######",
+        ]);
+        assert_eq!(find_path(&map, &[], (1, 1), (5, 1)), None);
+    }
+
+    #[test]
+    fn there_is_no_path_over_lava_or_chasms() {
+        let map = map(&[
+            "#######",
+            "#..=..#",
+            "#..:..#",
+            "#######",
+        ]);
+        assert_eq!(find_path(&map, &[], (1, 1), (5, 1)), None);
+    }
+
+    #[test]
+    fn the_safe_way_leads_out_of_dead_ends() {
+        // a monster at the mouth of a dead end, with the threat between it and a long hall
+        let map = map(&[
+            "############################################################",
+            "#######....................................................#",
+            "###........................................................#",
+            "#######....................................................#",
+            "############################################################",
+        ]);
+        let threat = (9, 2);
+        let safety = safety_map(&map, threat);
+        let safety_at = |(x, y): (i32, i32)| safety[x as usize][y as usize];
+        let safest_step = |(x, y): (i32, i32)| {
+            let here = safety_at((x, y)).unwrap();
+            (-1 .. 2).flat_map(|dx| (-1 .. 2).map(move |dy| (x + dx, y + dy)))
+                .filter(|&next| next != threat)
+                .filter_map(|next| safety_at(next).map(|value| (value, next)))
+                .filter(|&(value, _)| value < here)
+                .min()
+        };
+        let mut pos = (6, 2);
+        let mut way = vec![pos];
+        while let Some((_, next)) = safest_step(pos) {
+            pos = next;
+            way.push(pos);
+        }
+        // further from the threat, but a dead end
+        assert!(way.iter().all(|&(x, _)| x >= 6), "{:?}", way);
+        assert_eq!(pos.0, 58);
+    }
+}
@@ -0,0 +1,1207 @@
+//! Saving and loading games. Every save starts with a header recording the
+//! format version, so saves from older versions can be migrated on load.
+//! Saves live in named slots: one file per slot in a save directory.
+//!
+//! Saves are written as JSON or in the compact binary encoding of the
+//! `binary` module, optionally compressed. Any of them can be loaded: binary
+//! saves start with `BINARY_MAGIC`, anything else is read as JSON. Either way
+//! the header comes first, and can be read without the rest of the save.
+
+use std::cmp;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use rustc_serialize::{Decodable, Encodable, Encoder};
+use rustc_serialize::json::{self, Json, JsonEvent, StackElement};
+
+use binary;
+use game::{Game, GameMode, World, PLAYER};
+use object::Object;
+use {BOSS_DEPTH, FINAL_DEPTH, TORCH_RADIUS};
+
+/// The save format written by this version of the game. Bump it whenever a
+/// saved struct changes, and add a migration from the previous version.
+pub const FORMAT_VERSION: u32 = 16;
+
+/// The extension of save files in a save directory.
+pub const SAVE_EXTENSION: &'static str = "sav";
+
+/// The version of the game that wrote a save.
+pub const GAME_VERSION: &'static str = env!("CARGO_PKG_VERSION");
+
+/// The start of binary saves, followed by a byte saying whether the rest is
+/// compressed. Since format 16 `HEADER` comes first instead, with the length
+/// of the header as 4 bytes, little-endian, and the header itself, never
+/// compressed; the compression byte and the rest of the save follow.
+const BINARY_MAGIC: &'static [u8] = b"ROGUESAV";
+const UNCOMPRESSED: u8 = 0;
+const DEFLATE: u8 = 1;
+const HEADER: u8 = 2;
+
+/// Migrations between format versions: `MIGRATIONS[n]` turns a version `n`
+/// save into a version `n + 1` one.
+const MIGRATIONS: &'static [fn(Json) -> Result<Json, SaveError>] = &[
+    migrate_0_to_1,
+    migrate_1_to_2,
+    migrate_2_to_3,
+    migrate_3_to_4,
+    migrate_4_to_5,
+    migrate_5_to_6,
+    migrate_6_to_7,
+    migrate_7_to_8,
+    migrate_8_to_9,
+    migrate_9_to_10,
+    migrate_10_to_11,
+    migrate_11_to_12,
+    migrate_12_to_13,
+    migrate_13_to_14,
+    migrate_14_to_15,
+    migrate_15_to_16,
+];
+
+/// The start of every save: its version, and enough about the game to show
+/// it in a list of save slots without loading all of it.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct SaveHeader {
+    pub format_version: u32,
+    pub game_version: String,
+    pub character_level: i32,
+    pub dungeon_level: u32,
+    /// When the game was saved, in seconds since the Unix epoch. 0 if unknown.
+    pub saved_at: u64,
+}
+
+/// How a save is written to disk.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SaveFormat {
+    /// Plain JSON: big and slow, but easy to read and edit.
+    Json,
+    /// The compact binary encoding.
+    Binary,
+    /// The binary encoding, deflated.
+    Compressed,
+}
+
+impl Default for SaveFormat {
+    fn default() -> Self {
+        SaveFormat::Compressed
+    }
+}
+
+impl FromStr for SaveFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "json" => Ok(SaveFormat::Json),
+            "binary" => Ok(SaveFormat::Binary),
+            "compressed" => Ok(SaveFormat::Compressed),
+            _ => Err(format!("The save format must be json, binary or compressed, not '{}'.", s)),
+        }
+    }
+}
+
+/// A save file in a save directory.
+#[derive(Clone, Debug)]
+pub struct SaveSlot {
+    pub name: String,
+    pub path: PathBuf,
+    pub header: SaveHeader,
+}
+
+/// Why a saved game couldn't be loaded or written.
+#[derive(Debug)]
+pub enum SaveError {
+    /// There is no save at this path.
+    Missing(PathBuf),
+    /// The save exists but couldn't be read or written.
+    Io(io::Error),
+    /// The save isn't a valid saved game.
+    Corrupt(String),
+    /// The save was written by a newer version of the game.
+    Incompatible { format_version: u32, game_version: String },
+}
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SaveError::Missing(ref path) =>
+                write!(f, "There is no saved game at {}.", path.display()),
+            SaveError::Io(ref e) =>
+                write!(f, "The saved game could not be accessed: {}", e),
+            SaveError::Corrupt(ref reason) =>
+                write!(f, "The saved game is damaged and can't be loaded ({}).", reason),
+            SaveError::Incompatible { format_version, ref game_version } =>
+                write!(f, "The saved game comes from a newer version of the game ({}, save format {}) \
+                           and can't be loaded by this one ({}, save format {}).",
+                       game_version, format_version, GAME_VERSION, FORMAT_VERSION),
+        }
+    }
+}
+
+impl Error for SaveError {
+    fn description(&self) -> &str {
+        match *self {
+            SaveError::Missing(_) => "missing save",
+            SaveError::Io(_) => "save not accessible",
+            SaveError::Corrupt(_) => "corrupt save",
+            SaveError::Incompatible { .. } => "incompatible save",
+        }
+    }
+}
+
+impl From<io::Error> for SaveError {
+    fn from(e: io::Error) -> Self {
+        SaveError::Io(e)
+    }
+}
+
+/// What is written to disk, borrowing from the world being saved. Binary
+/// saves write the header on its own, ahead of the rest.
+#[derive(Clone, Copy)]
+struct SaveRef<'a> {
+    header: Option<&'a SaveHeader>,
+    objects: &'a [Object],
+    game: &'a Game,
+}
+
+impl<'a> Encodable for SaveRef<'a> {
+    fn encode<S: Encoder>(&self, s: &mut S) -> Result<(), S::Error> {
+        let header = self.header.is_some() as usize;
+        s.emit_struct("SaveFile", header + 2, |s| {
+            if let Some(header) = self.header {
+                s.emit_struct_field("header", 0, |s| header.encode(s))?;
+            }
+            s.emit_struct_field("objects", header, |s| self.objects.encode(s))?;
+            s.emit_struct_field("game", header + 1, |s| self.game.encode(s))?;
+            Ok(())
+        })
+    }
+}
+
+/// What is read back once the header has been checked.
+#[derive(RustcDecodable)]
+struct SaveFile {
+    objects: Vec<Object>,
+    game: Game,
+}
+
+/// The file a named slot is saved in.
+pub fn slot_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.{}", name, SAVE_EXTENSION))
+}
+
+/// All the readable saves in a directory, the most recent first.
+pub fn list_slots(dir: &Path) -> Vec<SaveSlot> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
+    };
+    let mut slots: Vec<SaveSlot> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == SAVE_EXTENSION))
+        .filter_map(|path| {
+            let name = path.file_stem()?.to_string_lossy().into_owned();
+            let header = read_header(&path).ok()?;
+            Some(SaveSlot { name: name, path: path, header: header })
+        })
+        .collect();
+    slots.sort_by(|a, b| b.header.saved_at.cmp(&a.header.saved_at).then(a.name.cmp(&b.name)));
+    slots
+}
+
+/// Save the game in the world's save format. The save is written atomically,
+/// so a crash while saving never damages the old save.
+pub fn save_game(world: &World, path: &Path) -> Result<(), SaveError> {
+    let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    write_atomically(path, &encode_save(world, saved_at)?)
+}
+
+/// What `save_game` writes, as if saved at `saved_at`.
+pub fn encode_save(world: &World, saved_at: u64) -> Result<Vec<u8>, SaveError> {
+    let header = SaveHeader {
+        format_version: FORMAT_VERSION,
+        game_version: GAME_VERSION.into(),
+        character_level: world.objects[PLAYER].level,
+        dungeon_level: world.game.dungeon_level,
+        saved_at: saved_at,
+    };
+    let save = SaveRef { header: None, objects: &world.objects, game: &world.game };
+    if world.save_format == SaveFormat::Json {
+        // the header is the first field, so it can still be read on its own
+        let save = SaveRef { header: Some(&header), ..save };
+        return json::encode(&save).map(String::into_bytes).map_err(|e| SaveError::Corrupt(e.to_string()));
+    }
+
+    let mut data = BINARY_MAGIC.to_vec();
+    data.push(HEADER);
+    let header = binary::encode(&header).map_err(SaveError::Corrupt)?;
+    data.extend_from_slice(&(header.len() as u32).to_le_bytes());
+    data.extend(header);
+    let save = binary::encode(&save).map_err(SaveError::Corrupt)?;
+    if world.save_format == SaveFormat::Binary {
+        data.push(UNCOMPRESSED);
+        data.extend(save);
+        return Ok(data);
+    }
+    data.push(DEFLATE);
+    let mut encoder = DeflateEncoder::new(data, Compression::default());
+    encoder.write_all(&save)?;
+    Ok(encoder.finish()?)
+}
+
+/// Write a file next to its final place and then rename it over it, so
+/// the old file stays whole if anything goes wrong. Missing directories are
+/// created.
+pub fn write_atomically(path: &Path, data: &[u8]) -> Result<(), SaveError> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    {
+        let mut file = File::create(&temp_path)?;
+        file.write_all(data)?;
+        file.sync_all()?;
+    }
+    fs::rename(&temp_path, path)?;
+    Ok(())
+}
+
+/// Load a saved game. Permadeath games are taken out of their slot, so the
+/// save can't be loaded again once the game is over.
+pub fn load_game(path: &Path) -> Result<World, SaveError> {
+    let save = read_save(path)?;
+    let mut decoder = json::Decoder::new(save);
+    let save = SaveFile::decode(&mut decoder).map_err(|e| SaveError::Corrupt(e.to_string()))?;
+    if save.game.mode == GameMode::Permadeath {
+        delete_save(path)?;
+    }
+    let mut world = World::from_parts(save.objects, save.game);
+    world.save_path = Some(path.to_path_buf());
+    Ok(world)
+}
+
+pub fn delete_save(path: &Path) -> Result<(), SaveError> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => Ok(result?),
+    }
+}
+
+/// Just the header of a save, e.g. to list it as a slot. Only the header is
+/// read, unless the save is too old to have a complete one at its start;
+/// then the whole save is read and migrated.
+pub fn read_header(path: &Path) -> Result<SaveHeader, SaveError> {
+    if let Some(header) = leading_header(path)? {
+        let (format_version, game_version) = header_version(&header)?;
+        if format_version > FORMAT_VERSION {
+            return Err(SaveError::Incompatible {
+                format_version: format_version,
+                game_version: game_version,
+            });
+        }
+        if let Ok(header) = SaveHeader::decode(&mut json::Decoder::new(header)) {
+            return Ok(header);
+        }
+    }
+    let header = match read_save(path)? {
+        Json::Object(mut save) => save.remove("header"),
+        _ => None,
+    };
+    let header = header.ok_or(corrupt("no header"))?;
+    let mut decoder = json::Decoder::new(header);
+    SaveHeader::decode(&mut decoder).map_err(|e| SaveError::Corrupt(e.to_string()))
+}
+
+/// The header at the start of a save, read without the rest of it: the
+/// header record of a binary save, or the first field of a JSON one. `None`
+/// if the save doesn't start with one, like binary saves from before format
+/// 16 and the array saves of format 0.
+fn leading_header(path: &Path) -> Result<Option<Json>, SaveError> {
+    let mut file = BufReader::new(open_save(path)?);
+    let mut start = vec![];
+    file.by_ref().take(BINARY_MAGIC.len() as u64 + 1).read_to_end(&mut start)?;
+
+    if !start.starts_with(BINARY_MAGIC) {
+        // the header is all ASCII, so the bytes up to its end are its chars
+        let chars = (&start[..]).chain(file).bytes().map(|byte| byte.map_or('\0', char::from));
+        return Ok(json_header(chars));
+    }
+    if start.last() != Some(&HEADER) {
+        return Ok(None);
+    }
+    let mut len = [0; 4];
+    file.read_exact(&mut len).map_err(|_| corrupt("no header"))?;
+    let mut header = vec![];
+    file.take(u32::from_le_bytes(len) as u64).read_to_end(&mut header)?;
+    binary::decode(&header).map(Some).map_err(SaveError::Corrupt)
+}
+
+/// Parse the `header` field at the start of a JSON save, and stop there.
+fn json_header<T: Iterator<Item = char>>(chars: T) -> Option<Json> {
+    let mut parser = json::Parser::new(chars);
+    if parser.next() != Some(JsonEvent::ObjectStart) || parser.next() != Some(JsonEvent::ObjectStart)
+            || !parser.stack().is_equal_to(&[StackElement::Key("header")]) {
+        return None;
+    }
+    let mut header = json::Object::new();
+    loop {
+        let value = match parser.next()? {
+            JsonEvent::ObjectEnd => return Some(Json::Object(header)),
+            JsonEvent::BooleanValue(v) => Json::Boolean(v),
+            JsonEvent::I64Value(v) => Json::I64(v),
+            JsonEvent::U64Value(v) => Json::U64(v),
+            JsonEvent::F64Value(v) => Json::F64(v),
+            JsonEvent::StringValue(v) => Json::String(v),
+            JsonEvent::NullValue => Json::Null,
+            // headers hold nothing nested
+            _ => return None,
+        };
+        match parser.stack().top() {
+            Some(StackElement::Key(key)) => header.insert(key.into(), value),
+            _ => return None,
+        };
+    }
+}
+
+/// A save as it is stored, whatever its format, without migrating it. This
+/// is what `savedump` shows.
+pub fn dump_save(path: &Path) -> Result<Json, SaveError> {
+    let mut data = vec![];
+    open_save(path)?.read_to_end(&mut data)?;
+
+    if !data.starts_with(BINARY_MAGIC) {
+        let json_save_state = String::from_utf8(data).map_err(|_| corrupt("not a save"))?;
+        return Json::from_str(&json_save_state).map_err(|e| SaveError::Corrupt(e.to_string()));
+    }
+    let mut payload = &data[BINARY_MAGIC.len()..];
+    let mut header = None;
+    if payload.first() == Some(&HEADER) {
+        if payload.len() < 5 {
+            return Err(corrupt("no header"));
+        }
+        let len = u32::from_le_bytes([payload[1], payload[2], payload[3], payload[4]]) as usize;
+        if payload.len() - 5 < len {
+            return Err(corrupt("no header"));
+        }
+        header = Some(binary::decode(&payload[5..5 + len]).map_err(SaveError::Corrupt)?);
+        payload = &payload[5 + len..];
+    }
+    let save = match payload.first() {
+        Some(&UNCOMPRESSED) => binary::decode(&payload[1..]).map_err(SaveError::Corrupt)?,
+        Some(&DEFLATE) => {
+            let mut inflated = vec![];
+            DeflateDecoder::new(&payload[1..]).read_to_end(&mut inflated)
+                .map_err(|e| SaveError::Corrupt(e.to_string()))?;
+            binary::decode(&inflated).map_err(SaveError::Corrupt)?
+        }
+        _ => return Err(corrupt("unknown compression")),
+    };
+    match (header, save) {
+        (None, save) => Ok(save),
+        (Some(header), Json::Object(mut save)) => {
+            save.insert("header".into(), header);
+            Ok(Json::Object(save))
+        }
+        _ => Err(corrupt("the save is not an object")),
+    }
+}
+
+fn open_save(path: &Path) -> Result<File, SaveError> {
+    File::open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::NotFound => SaveError::Missing(path.to_path_buf()),
+        _ => SaveError::Io(e),
+    })
+}
+
+/// Read a save and migrate it to the current format.
+fn read_save(path: &Path) -> Result<Json, SaveError> {
+    let mut save = dump_save(path)?;
+    let (format_version, game_version) = save_version(&save)?;
+    if format_version > FORMAT_VERSION {
+        return Err(SaveError::Incompatible {
+            format_version: format_version,
+            game_version: game_version,
+        });
+    }
+    for migration in &MIGRATIONS[format_version as usize..] {
+        save = migration(save)?;
+    }
+    Ok(save)
+}
+
+/// The format and game version of a save. The first saves had no header and
+/// were just an `[objects, game]` array, which counts as format 0.
+fn save_version(save: &Json) -> Result<(u32, String), SaveError> {
+    if save.is_array() {
+        return Ok((0, "0.1.0".into()));
+    }
+    header_version(save.find("header").ok_or(SaveError::Corrupt("no header".into()))?)
+}
+
+/// The format and game version a header records.
+fn header_version(header: &Json) -> Result<(u32, String), SaveError> {
+    let format_version = header.find("format_version")
+        .and_then(|version| version.as_u64())
+        .ok_or(SaveError::Corrupt("no format version".into()))?;
+    let game_version = header.find("game_version")
+        .and_then(|version| version.as_string())
+        .unwrap_or("unknown");
+    Ok((format_version as u32, game_version.into()))
+}
+
+fn corrupt(reason: &str) -> SaveError {
+    SaveError::Corrupt(reason.into())
+}
+
+/// Format 1 added the header, and the seed, RNG state and wizard flag to the
+/// game. Old games get a fixed seed, as the one they were made from is lost.
+fn migrate_0_to_1(save: Json) -> Result<Json, SaveError> {
+    let mut parts = match save {
+        Json::Array(parts) => parts,
+        _ => return Err(corrupt("expected an [objects, game] array")),
+    };
+    if parts.len() != 2 {
+        return Err(corrupt("expected an [objects, game] array"));
+    }
+    let mut game = match parts.pop() {
+        Some(Json::Object(game)) => game,
+        _ => return Err(corrupt("the game is not an object")),
+    };
+    let objects = parts.pop().unwrap();
+
+    game.insert("seed".into(), Json::U64(0));
+    let mut rng = json::Object::new();
+    rng.insert("state".into(), Json::U64(0));
+    game.insert("rng".into(), Json::Object(rng));
+    game.insert("wizard".into(), Json::Boolean(false));
+
+    let mut header = json::Object::new();
+    header.insert("format_version".into(), Json::U64(1));
+    header.insert("game_version".into(), Json::String(GAME_VERSION.into()));
+
+    let mut save = json::Object::new();
+    save.insert("header".into(), Json::Object(header));
+    save.insert("objects".into(), objects);
+    save.insert("game".into(), Json::Object(game));
+    Ok(Json::Object(save))
+}
+
+/// Format 2 added what the save slot list shows to the header.
+fn migrate_1_to_2(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    let character_level = save.get("objects")
+        .and_then(|objects| objects.as_array())
+        .and_then(|objects| objects.get(PLAYER))
+        .and_then(|player| player.find("level"))
+        .and_then(|level| level.as_i64())
+        .ok_or(corrupt("no player"))?;
+    let dungeon_level = save.get("game")
+        .and_then(|game| game.find("dungeon_level"))
+        .and_then(|level| level.as_u64())
+        .ok_or(corrupt("no dungeon level"))?;
+
+    let header = match save.get_mut("header") {
+        Some(&mut Json::Object(ref mut header)) => header,
+        _ => return Err(corrupt("no header")),
+    };
+    header.insert("character_level".into(), Json::I64(character_level));
+    header.insert("dungeon_level".into(), Json::U64(dungeon_level));
+    header.insert("saved_at".into(), Json::U64(0));
+    set_format_version(&mut save, 2)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 3 added the game mode. Older games keep their saves, as before.
+fn migrate_2_to_3(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => {
+            game.insert("mode".into(), Json::String("Exploration".into()));
+        }
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 3)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 4 kept the levels the player left, and added up stairs. The level
+/// the player is on gets its up stairs where the player stands; the levels
+/// above it are lost, and will be generated anew.
+fn migrate_3_to_4(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => {
+            game.insert("levels".into(), Json::Object(json::Object::new()));
+        }
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    let objects = match save.get_mut("objects") {
+        Some(&mut Json::Array(ref mut objects)) => objects,
+        _ => return Err(corrupt("the objects are not an array")),
+    };
+    let player_pos = objects.get(PLAYER)
+        .and_then(|player| Some((player.find("x")?.clone(), player.find("y")?.clone())))
+        .ok_or(corrupt("no player"))?;
+    let mut up_stairs = None;
+    for object in objects.iter_mut() {
+        if let Json::Object(ref mut object) = *object {
+            if object.get("name").and_then(|name| name.as_string()) == Some("stairs") {
+                object.insert("name".into(), Json::String("down stairs".into()));
+                let mut stairs = object.clone();
+                stairs.insert("name".into(), Json::String("up stairs".into()));
+                stairs.insert("char".into(), Json::String("<".into()));
+                stairs.insert("x".into(), player_pos.0.clone());
+                stairs.insert("y".into(), player_pos.1.clone());
+                up_stairs = Some(Json::Object(stairs));
+            }
+        }
+    }
+    objects.extend(up_stairs);
+    set_format_version(&mut save, 4)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 5 added the victory condition. Games that are already past the
+/// final depth find the amulet on the next level they reach.
+fn migrate_4_to_5(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => {
+            let dungeon_level = game.get("dungeon_level")
+                .and_then(|level| level.as_u64())
+                .ok_or(corrupt("no dungeon level"))?;
+            let final_depth = if dungeon_level < FINAL_DEPTH as u64 {
+                FINAL_DEPTH as u64
+            } else {
+                dungeon_level + 1
+            };
+            game.insert("final_depth".into(), Json::U64(final_depth));
+            game.insert("won".into(), Json::Boolean(false));
+        }
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 5)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 6 counted turns and kills, for the score. Older games start
+/// counting from zero.
+fn migrate_5_to_6(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => {
+            game.insert("turns".into(), Json::U64(0));
+            game.insert("kills".into(), Json::U64(0));
+        }
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 6)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 7 added doors to the tiles. Levels made before have none.
+fn migrate_6_to_7(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => for_each_tile(game, |tile| {
+            tile.insert("door".into(), Json::Null);
+        })?,
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 7)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 8 added traps to the tiles. Levels made before have none.
+fn migrate_7_to_8(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => for_each_tile(game, |tile| {
+            tile.insert("trap".into(), Json::Null);
+        })?,
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 8)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 9 gave every tile a terrain. Before, tiles were walls or floor,
+/// doorways included.
+fn migrate_8_to_9(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => for_each_tile(game, |tile| {
+            let blocked = tile.get("blocked").and_then(Json::as_boolean).unwrap_or(false);
+            let door = tile.get("door").map_or(false, |door| !door.is_null());
+            let terrain = if blocked && !door { "Wall" } else { "Floor" };
+            tile.insert("terrain".into(), Json::String(terrain.into()));
+        })?,
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 9)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 10 added secret doors, which older games can't load. Levels made
+/// before have none, so there is nothing to change but the version.
+fn migrate_9_to_10(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    set_format_version(&mut save, 10)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 11 added level themes and the boss's lair. The levels made before
+/// were all plain dungeon, and games already past the lair's depth find the
+/// boss on the next new level they reach.
+fn migrate_10_to_11(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    match save.get_mut("game") {
+        Some(&mut Json::Object(ref mut game)) => {
+            let dungeon_level = game.get("dungeon_level")
+                .and_then(|level| level.as_u64())
+                .ok_or(corrupt("no dungeon level"))?;
+            let deepest = match game.get_mut("levels") {
+                Some(&mut Json::Object(ref mut levels)) => {
+                    for level in levels.values_mut() {
+                        match *level {
+                            Json::Object(ref mut level) => {
+                                level.insert("theme".into(), Json::String("Dungeon".into()));
+                            }
+                            _ => return Err(corrupt("a level is not an object")),
+                        }
+                    }
+                    levels.keys().filter_map(|depth| depth.parse().ok()).fold(dungeon_level, cmp::max)
+                }
+                _ => return Err(corrupt("no levels")),
+            };
+            game.insert("theme".into(), Json::String("Dungeon".into()));
+            game.insert("boss_depth".into(), Json::U64(cmp::max(BOSS_DEPTH as u64, deepest + 1)));
+        }
+        _ => return Err(corrupt("the game is not an object")),
+    }
+    set_format_version(&mut save, 11)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 12 added monsters that sleep, wander, hunt and search, which older
+/// games can't load. Monsters saved before stay as they were, so there is
+/// nothing to change but the version.
+fn migrate_11_to_12(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    set_format_version(&mut save, 12)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 13 gave monsters senses of their own. Before, they noticed the
+/// player as soon as the player could see them, so that's what the monsters
+/// saved before keep doing: seeing as far as the torch reaches, and noticing
+/// at once.
+fn migrate_12_to_13(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    let mut senses = json::Object::new();
+    senses.insert("sight_radius".into(), Json::I64(TORCH_RADIUS as i64));
+    senses.insert("hearing_radius".into(), Json::I64(0));
+    senses.insert("perception".into(), Json::U64(100));
+    for_each_object(&mut save, |object| {
+        let senses = match object.get("ai") {
+            Some(&Json::Null) | None => Json::Null,
+            Some(_) => Json::Object(senses.clone()),
+        };
+        object.insert("senses".into(), senses);
+    })?;
+    set_format_version(&mut save, 13)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 14 added monsters that shoot. None of those saved before do.
+fn migrate_13_to_14(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    for_each_object(&mut save, |object| {
+        object.insert("ranged".into(), Json::Null);
+    })?;
+    set_format_version(&mut save, 14)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 15 added morale, so that monsters run away, and packs. Monsters
+/// saved before keep fighting to the death, on their own.
+fn migrate_14_to_15(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    for_each_object(&mut save, |object| {
+        object.insert("morale".into(), Json::Null);
+    })?;
+    set_format_version(&mut save, 15)?;
+    Ok(Json::Object(save))
+}
+
+/// Format 16 wrote the header of binary saves ahead of the rest, on its own,
+/// so it can be read without inflating and decoding the whole save. Older
+/// games can't load those; what the save holds is the same, so there is
+/// nothing to change but the version.
+fn migrate_15_to_16(save: Json) -> Result<Json, SaveError> {
+    let mut save = match save {
+        Json::Object(save) => save,
+        _ => return Err(corrupt("the save is not an object")),
+    };
+    set_format_version(&mut save, 16)?;
+    Ok(Json::Object(save))
+}
+
+/// Mark a save as being in the given format, once it has been migrated to it.
+fn set_format_version(save: &mut json::Object, version: u64) -> Result<(), SaveError> {
+    match save.get_mut("header") {
+        Some(&mut Json::Object(ref mut header)) => {
+            header.insert("format_version".into(), Json::U64(version));
+            Ok(())
+        }
+        _ => Err(corrupt("no header")),
+    }
+}
+
+/// Change every object of a game: those on the level the player is on,
+/// those on the levels kept from before, and those in the inventory.
+fn for_each_object<F: FnMut(&mut json::Object)>(save: &mut json::Object, mut change: F)
+        -> Result<(), SaveError> {
+    let mut lists = vec![];
+    for (name, value) in save.iter_mut() {
+        match (name.as_str(), value) {
+            ("objects", objects) => lists.push(objects),
+            ("game", &mut Json::Object(ref mut game)) => {
+                for (name, value) in game.iter_mut() {
+                    match (name.as_str(), value) {
+                        ("inventory", inventory) => lists.push(inventory),
+                        ("levels", &mut Json::Object(ref mut levels)) => {
+                            for level in levels.values_mut() {
+                                match *level {
+                                    Json::Object(ref mut level) => lists.extend(level.get_mut("objects")),
+                                    _ => return Err(corrupt("a level is not an object")),
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for list in lists {
+        let objects = match *list {
+            Json::Array(ref mut objects) => objects,
+            _ => return Err(corrupt("a list of objects is not an array")),
+        };
+        for object in objects {
+            match *object {
+                Json::Object(ref mut object) => change(object),
+                _ => return Err(corrupt("an object is not a JSON object")),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Change every tile of a game: those of the level the player is on, and
+/// those of the levels kept from before.
+fn for_each_tile<F: FnMut(&mut json::Object)>(game: &mut json::Object, mut change: F)
+        -> Result<(), SaveError> {
+    let mut maps = vec![];
+    for (name, value) in game.iter_mut() {
+        match (name.as_str(), value) {
+            ("map", map) => maps.push(map),
+            ("levels", &mut Json::Object(ref mut levels)) => {
+                for level in levels.values_mut() {
+                    match *level {
+                        Json::Object(ref mut level) => maps.extend(level.get_mut("map")),
+                        _ => return Err(corrupt("a level is not an object")),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    for map in maps {
+        let columns = match *map {
+            Json::Array(ref mut columns) => columns,
+            _ => return Err(corrupt("a map is not an array")),
+        };
+        for column in columns {
+            let tiles = match *column {
+                Json::Array(ref mut tiles) => tiles,
+                _ => return Err(corrupt("a map column is not an array")),
+            };
+            for tile in tiles {
+                match *tile {
+                    Json::Object(ref mut tile) => change(tile),
+                    _ => return Err(corrupt("a tile is not an object")),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, process};
+
+    use super::*;
+    use map::Terrain;
+
+    /// A save as the first versioned game wrote it: a 4 by 4 level walled
+    /// in, with the player, an orc and the stairs on the floor inside, and a
+    /// dagger in the inventory.
+    fn v1_save() -> Json {
+        let mut map = vec![];
+        for x in 0..4 {
+            let column: Vec<_> = (0..4).map(|y| {
+                let wall = x == 0 || y == 0 || x == 3 || y == 3;
+                format!(r#"{{"blocked":{0},"block_sight":{0},"explored":false}}"#, wall)
+            }).collect();
+            map.push(format!("[{}]", column.join(",")));
+        }
+        let save = r#"{
+            "header": {"format_version": 1, "game_version": "0.1.0"},
+            "objects": [
+                {"x": 1, "y": 1, "char": "@", "color": {"r": 255, "g": 255, "b": 255},
+                 "name": "player", "blocks": true, "alive": true,
+                 "fighter": {"base_max_hp": 100, "hp": 73, "base_defense": 1, "base_power": 2,
+                             "on_death": "Player", "xp": 35},
+                 "ai": null, "item": null, "always_visible": false, "level": 2, "equipment": null},
+                {"x": 2, "y": 2, "char": "o", "color": {"r": 63, "g": 127, "b": 63},
+                 "name": "orc", "blocks": true, "alive": true,
+                 "fighter": {"base_max_hp": 20, "hp": 20, "base_defense": 0, "base_power": 4,
+                             "on_death": "Monster", "xp": 35},
+                 "ai": "Basic", "item": null, "always_visible": false, "level": 1, "equipment": null},
+                {"x": 2, "y": 1, "char": ">", "color": {"r": 255, "g": 255, "b": 255},
+                 "name": "stairs", "blocks": false, "alive": false, "fighter": null,
+                 "ai": null, "item": null, "always_visible": true, "level": 1, "equipment": null}
+            ],
+            "game": {
+                "map": MAP,
+                "log": [["Welcome stranger!", {"r": 255, "g": 0, "b": 0}]],
+                "inventory": [
+                    {"x": 0, "y": 0, "char": "-", "color": {"r": 0, "g": 191, "b": 255},
+                     "name": "dagger", "blocks": false, "alive": false, "fighter": null,
+                     "ai": null, "item": "Sword", "always_visible": false, "level": 1,
+                     "equipment": {"slot": "LeftHand", "equipped": true, "power_bonus": 2,
+                                   "defense_bonus": 0, "max_hp_bonus": 0}}
+                ],
+                "dungeon_level": 3,
+                "seed": 42,
+                "rng": {"state": 12345},
+                "wizard": false
+            }
+        }"#;
+        Json::from_str(&save.replace("MAP", &format!("[{}]", map.join(",")))).unwrap()
+    }
+
+    /// Run a save in format `from` through the migrations up to format `to`.
+    fn migrate(mut save: Json, from: u32, to: u32) -> Json {
+        for migration in &MIGRATIONS[from as usize..to as usize] {
+            save = migration(save).unwrap();
+        }
+        save
+    }
+
+    fn tiles(save: &Json) -> Vec<&Json> {
+        save["game"]["map"].as_array().unwrap().iter()
+            .flat_map(|column| column.as_array().unwrap())
+            .collect()
+    }
+
+    fn objects(save: &Json) -> Vec<&Json> {
+        save["objects"].as_array().unwrap().iter()
+            .chain(save["game"]["inventory"].as_array().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn v1_save_migrates_to_the_current_format() {
+        let save = migrate(v1_save(), 1, FORMAT_VERSION);
+        assert_eq!(save_version(&save).unwrap().0, FORMAT_VERSION);
+        let save = SaveFile::decode(&mut json::Decoder::new(save)).unwrap();
+        let world = World::from_parts(save.objects, save.game);
+        assert_eq!(world.player().pos(), (1, 1));
+        assert_eq!(world.player().fighter.as_ref().unwrap().hp, 73);
+        assert_eq!(world.game.dungeon_level, 3);
+        assert_eq!(world.game.seed, 42);
+        assert_eq!(world.game.inventory[0].name, "dagger");
+        assert_eq!(world.game.mode, GameMode::Exploration);
+        assert_eq!(world.game.map[0][0].terrain, Terrain::Wall);
+        assert_eq!(world.game.map[1][1].terrain, Terrain::Floor);
+    }
+
+    #[test]
+    fn migrate_0_to_1_adds_the_header_and_seed() {
+        let v1 = v1_save();
+        let mut game = v1["game"].as_object().unwrap().clone();
+        for name in &["seed", "rng", "wizard"] {
+            game.remove(*name);
+        }
+        let v0 = Json::Array(vec![v1["objects"].clone(), Json::Object(game)]);
+        let save = migrate(v0, 0, 1);
+        assert_eq!(save_version(&save).unwrap().0, 1);
+        assert_eq!(save["objects"], v1["objects"]);
+        assert_eq!(save["game"]["seed"], Json::U64(0));
+        assert_eq!(save["game"]["rng"]["state"], Json::U64(0));
+        assert_eq!(save["game"]["wizard"], Json::Boolean(false));
+        assert!(migrate_0_to_1(Json::Array(vec![])).is_err());
+        assert!(migrate_0_to_1(Json::Null).is_err());
+    }
+
+    #[test]
+    fn migrate_1_to_2_fills_in_the_header() {
+        let save = migrate(v1_save(), 1, 2);
+        let header = SaveHeader::decode(&mut json::Decoder::new(save["header"].clone())).unwrap();
+        assert_eq!(header.format_version, 2);
+        assert_eq!(header.character_level, 2);
+        assert_eq!(header.dungeon_level, 3);
+        assert_eq!(header.saved_at, 0);
+    }
+
+    #[test]
+    fn migrate_2_to_3_keeps_saves_as_before() {
+        let save = migrate(v1_save(), 1, 3);
+        assert_eq!(save["game"]["mode"].as_string(), Some("Exploration"));
+    }
+
+    #[test]
+    fn migrate_3_to_4_adds_up_stairs_under_the_player() {
+        let save = migrate(v1_save(), 1, 4);
+        assert_eq!(save["game"]["levels"], Json::Object(json::Object::new()));
+        let named = |name: &str| objects(&save).into_iter()
+            .filter(|object| object["name"].as_string() == Some(name))
+            .map(|object| (object["x"].as_i64().unwrap(), object["y"].as_i64().unwrap()))
+            .collect::<Vec<_>>();
+        assert_eq!(named("stairs"), vec![]);
+        assert_eq!(named("down stairs"), vec![(2, 1)]);
+        assert_eq!(named("up stairs"), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn migrate_4_to_5_puts_the_amulet_below_the_player() {
+        let save = migrate(v1_save(), 1, 5);
+        assert_eq!(save["game"]["final_depth"], Json::U64(FINAL_DEPTH as u64));
+        assert_eq!(save["game"]["won"], Json::Boolean(false));
+
+        let mut deep = migrate(v1_save(), 1, 4);
+        deep.as_object_mut().unwrap().get_mut("game").unwrap().as_object_mut().unwrap()
+            .insert("dungeon_level".into(), Json::U64(FINAL_DEPTH as u64 + 2));
+        let deep = migrate(deep, 4, 5);
+        assert_eq!(deep["game"]["final_depth"], Json::U64(FINAL_DEPTH as u64 + 3));
+    }
+
+    #[test]
+    fn migrate_5_to_6_starts_counting() {
+        let save = migrate(v1_save(), 1, 6);
+        assert_eq!(save["game"]["turns"], Json::U64(0));
+        assert_eq!(save["game"]["kills"], Json::U64(0));
+    }
+
+    #[test]
+    fn migrate_6_to_7_and_7_to_8_add_no_doors_or_traps() {
+        let save = migrate(v1_save(), 1, 8);
+        for tile in tiles(&save) {
+            assert_eq!(tile["door"], Json::Null);
+            assert_eq!(tile["trap"], Json::Null);
+        }
+    }
+
+    #[test]
+    fn migrate_8_to_9_turns_blocked_tiles_into_walls() {
+        let save = migrate(v1_save(), 1, 9);
+        for tile in tiles(&save) {
+            let terrain = if tile["blocked"] == Json::Boolean(true) { "Wall" } else { "Floor" };
+            assert_eq!(tile["terrain"].as_string(), Some(terrain));
+        }
+    }
+
+    #[test]
+    fn migrations_without_changes_only_bump_the_version() {
+        for &from in &[9, 11, 15] {
+            let before = migrate(v1_save(), 1, from);
+            let mut after = migrate(before.clone(), from, from + 1);
+            assert_eq!(save_version(&after).unwrap().0, from + 1);
+            set_format_version(after.as_object_mut().unwrap(), from as u64).unwrap();
+            assert_eq!(after, before);
+        }
+    }
+
+    #[test]
+    fn migrate_10_to_11_makes_everything_a_dungeon() {
+        let save = migrate(v1_save(), 1, 11);
+        assert_eq!(save["game"]["theme"].as_string(), Some("Dungeon"));
+        assert_eq!(save["game"]["boss_depth"], Json::U64(BOSS_DEPTH as u64));
+    }
+
+    #[test]
+    fn migrate_12_to_13_gives_monsters_torchlight_senses() {
+        let save = migrate(v1_save(), 1, 13);
+        for object in objects(&save) {
+            if object["ai"].is_null() {
+                assert_eq!(object["senses"], Json::Null);
+            } else {
+                assert_eq!(object["senses"]["sight_radius"].as_i64(), Some(TORCH_RADIUS as i64));
+                assert_eq!(object["senses"]["hearing_radius"].as_i64(), Some(0));
+                assert_eq!(object["senses"]["perception"].as_u64(), Some(100));
+            }
+        }
+    }
+
+    #[test]
+    fn migrations_14_and_15_reach_every_object() {
+        let mut save = migrate(v1_save(), 1, 13);
+        // keep a copy of the level as if the player had come up from it
+        let level = {
+            let game = save["game"].as_object().unwrap();
+            let mut level = json::Object::new();
+            level.insert("map".into(), game["map"].clone());
+            level.insert("theme".into(), game["theme"].clone());
+            level.insert("objects".into(), save["objects"].clone());
+            level
+        };
+        match save.as_object_mut().unwrap().get_mut("game") {
+            Some(&mut Json::Object(ref mut game)) => {
+                let mut levels = json::Object::new();
+                levels.insert("4".into(), Json::Object(level));
+                game.insert("levels".into(), Json::Object(levels));
+            }
+            _ => unreachable!(),
+        }
+        let save = migrate(save, 13, 15);
+        let kept = save["game"]["levels"]["4"]["objects"].as_array().unwrap();
+        for object in objects(&save).into_iter().chain(kept) {
+            assert_eq!(object["ranged"], Json::Null);
+            assert_eq!(object["morale"], Json::Null);
+        }
+        let save = SaveFile::decode(&mut json::Decoder::new(save)).unwrap();
+        assert_eq!(save.game.levels[&4].objects.len(), 4);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("roguelike-{}-{}.{}", process::id(), name, SAVE_EXTENSION))
+    }
+
+    #[test]
+    fn headers_are_read_on_their_own() {
+        let mut world = World::new(7, GameMode::Exploration);
+        world.game.dungeon_level = 4;
+        world.objects[PLAYER].level = 3;
+        for &format in &[SaveFormat::Json, SaveFormat::Binary, SaveFormat::Compressed] {
+            world.save_format = format;
+            let path = temp_path(&format!("header-{:?}", format));
+            save_game(&world, &path).unwrap();
+
+            let header = leading_header(&path).unwrap().expect("no leading header");
+            let header = SaveHeader::decode(&mut json::Decoder::new(header)).unwrap();
+            assert_eq!(header.format_version, FORMAT_VERSION);
+            assert_eq!(header.character_level, 3);
+            assert_eq!(header.dungeon_level, 4);
+            assert_eq!(read_header(&path).unwrap(), header);
+            assert_eq!(dump_save(&path).unwrap()["header"]["dungeon_level"], Json::U64(4));
+            assert_eq!(load_game(&path).unwrap().game.dungeon_level, 4);
+            fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn headers_of_older_saves_are_still_read() {
+        let mut world = World::new(7, GameMode::Exploration);
+        world.save_format = SaveFormat::Json;
+        let save = migrate(v1_save(), 1, FORMAT_VERSION);
+        let header = SaveHeader::decode(&mut json::Decoder::new(save["header"].clone())).unwrap();
+
+        // a binary save from before format 16, with the header in the rest
+        let path = temp_path("header-old-binary");
+        let mut data = BINARY_MAGIC.to_vec();
+        data.push(UNCOMPRESSED);
+        data.extend(binary::encode(&save).unwrap());
+        write_atomically(&path, &data).unwrap();
+        assert!(leading_header(&path).unwrap().is_none());
+        assert_eq!(read_header(&path).unwrap(), header);
+
+        // a JSON save as savedump prints it, with the header after the game
+        let path = temp_path("header-dumped");
+        write_atomically(&path, save.pretty().to_string().as_bytes()).unwrap();
+        assert!(leading_header(&path).unwrap().is_none());
+        assert_eq!(read_header(&path).unwrap(), header);
+
+        // a format 1 header lacks what the slot list shows
+        let v1 = v1_save();
+        let path = temp_path("header-v1");
+        let text = format!(r#"{{"header":{},"objects":{},"game":{}}}"#, v1["header"], v1["objects"], v1["game"]);
+        write_atomically(&path, text.as_bytes()).unwrap();
+        assert_eq!(leading_header(&path).unwrap().unwrap(), v1["header"]);
+        assert_eq!(read_header(&path).unwrap(), header);
+
+        // a save from a newer game can't be loaded
+        let path = temp_path("header-newer");
+        let text = String::from_utf8(encode_save(&world, 0).unwrap()).unwrap().replace(
+            &format!(r#""format_version":{}"#, FORMAT_VERSION),
+            &format!(r#""format_version":{}"#, FORMAT_VERSION + 1));
+        write_atomically(&path, text.as_bytes()).unwrap();
+        match read_header(&path) {
+            Err(SaveError::Incompatible { format_version, .. }) => assert_eq!(format_version, FORMAT_VERSION + 1),
+            other => panic!("expected an incompatible save, got {:?}", other),
+        }
+
+        for name in &["header-old-binary", "header-dumped", "header-v1", "header-newer"] {
+            fs::remove_file(temp_path(name)).unwrap();
+        }
+    }
+}
@@ -0,0 +1,249 @@
+//! The high-score table, kept as JSON next to the save slots, and the morgue
+//! files written when a game ends.
+
+use std::fmt::{self, Write};
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use rustc_serialize::json;
+
+use game::{World, PLAYER};
+use map::{map_size, Door};
+use save::{write_atomically, SaveError};
+
+/// How many games the high-score table keeps.
+pub const MAX_HIGH_SCORES: usize = 20;
+
+/// How many of the last messages a morgue file shows.
+const MORGUE_MESSAGES: usize = 20;
+
+/// A finished game in the high-score table.
+#[derive(Clone, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub struct HighScore {
+    pub score: u32,
+    pub won: bool,
+    pub deepest_level: u32,
+    pub character_level: i32,
+    pub xp: i32,
+    pub kills: u32,
+    pub turns: u32,
+    pub seed: u64,
+    /// When the game ended, in seconds since the Unix epoch.
+    pub ended_at: u64,
+}
+
+impl HighScore {
+    pub fn new(world: &World) -> Self {
+        HighScore {
+            score: world.score(),
+            won: world.game.won,
+            deepest_level: world.deepest_level(),
+            character_level: world.player().level,
+            xp: world.total_xp(),
+            kills: world.game.kills,
+            turns: world.game.turns,
+            seed: world.game.seed,
+            ended_at: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+        }
+    }
+}
+
+/// The high-score table of a save directory.
+pub fn high_scores_path(dir: &Path) -> PathBuf {
+    dir.join("highscores.json")
+}
+
+/// The high scores, best first. There are none until a game has ended.
+pub fn load_high_scores(path: &Path) -> Result<Vec<HighScore>, SaveError> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut scores = String::new();
+    file.read_to_string(&mut scores)?;
+    json::decode(&scores).map_err(|e| SaveError::Corrupt(e.to_string()))
+}
+
+/// Add a finished game to the high-score table. Returns its place in the
+/// table, counting from 0, or `None` if it didn't make it in.
+pub fn record_high_score(path: &Path, entry: HighScore) -> Result<Option<usize>, SaveError> {
+    let mut scores = load_high_scores(path)?;
+    let rank = scores.iter().position(|other| entry.score > other.score).unwrap_or(scores.len());
+    if rank >= MAX_HIGH_SCORES {
+        return Ok(None);
+    }
+    scores.insert(rank, entry);
+    scores.truncate(MAX_HIGH_SCORES);
+    let scores = json::encode(&scores).map_err(|e| SaveError::Corrupt(e.to_string()))?;
+    write_atomically(path, scores.as_bytes())?;
+    Ok(Some(rank))
+}
+
+/// A plain-text account of a finished game: the final stats, equipment,
+/// inventory, last messages and the final map.
+pub fn morgue(world: &World) -> String {
+    let mut text = String::new();
+    write_morgue_text(world, &mut text).expect("writing to a String can't fail");
+    text
+}
+
+fn write_morgue_text(world: &World, text: &mut String) -> fmt::Result {
+    let game = &world.game;
+    let player = world.player();
+
+    let outcome = if game.won {
+        "Escaped the Tombs of the Ancient Kings with their amulet".to_string()
+    } else if world.player_alive() {
+        format!("Left the game on dungeon level {}", game.dungeon_level)
+    } else {
+        format!("Died on dungeon level {}", game.dungeon_level)
+    };
+    writeln!(text, "Tombs of the Ancient Kings\n\n{}.\n", outcome)?;
+    writeln!(text, "Score: {}", world.score())?;
+    writeln!(text, "Character level: {} ({} experience)", player.level, world.total_xp())?;
+    writeln!(text, "Deepest level: {} (amulet on {})", world.deepest_level(), game.final_depth)?;
+    writeln!(text, "Kills: {}", game.kills)?;
+    writeln!(text, "Turns: {}", game.turns)?;
+    writeln!(text, "HP: {}/{}", player.fighter.map_or(0, |f| f.hp), player.max_hp(game))?;
+    writeln!(text, "Attack: {}", player.power(game))?;
+    writeln!(text, "Defense: {}", player.defense(game))?;
+    writeln!(text, "Seed: {}", game.seed)?;
+
+    writeln!(text, "\nEquipment:")?;
+    let equipped = player.get_all_equipped(game);
+    if equipped.is_empty() {
+        writeln!(text, "  nothing")?;
+    }
+    for equipment in &equipped {
+        writeln!(text, "  {}: +{} attack, +{} defense, +{} HP", equipment.slot,
+                 equipment.power_bonus, equipment.defense_bonus, equipment.max_hp_bonus)?;
+    }
+
+    writeln!(text, "\nInventory:")?;
+    if game.inventory.is_empty() {
+        writeln!(text, "  nothing")?;
+    }
+    for item in &game.inventory {
+        match item.equipment {
+            Some(equipment) if equipment.equipped =>
+                writeln!(text, "  {} (on {})", item.name, equipment.slot)?,
+            _ => writeln!(text, "  {}", item.name)?,
+        }
+    }
+
+    writeln!(text, "\nLast messages:")?;
+    let first_message = game.log.len().saturating_sub(MORGUE_MESSAGES);
+    for &(ref message, _) in &game.log[first_message..] {
+        writeln!(text, "  {}", message)?;
+    }
+
+    writeln!(text, "\nFinal map:")?;
+    let (width, height) = map_size(&game.map);
+    for y in 0..height {
+        let mut line: Vec<char> = (0..width)
+            .map(|x| {
+                let tile = &game.map[x as usize][y as usize];
+                match (tile.door, tile.trap) {
+                    (Some(Door::Open), _) => '\'',
+                    (Some(Door::Secret), _) => tile.terrain.glyph(),
+                    (Some(_), _) => '+',
+                    (None, Some(trap)) if trap.known => '^',
+                    (None, _) => tile.terrain.glyph(),
+                }
+            })
+            .collect();
+        // the player goes last, so it's drawn over whatever it stands on
+        for object in world.objects.iter().skip(PLAYER + 1).chain(Some(player)) {
+            if object.y == y {
+                line[object.x as usize] = object.char;
+            }
+        }
+        let line: String = line.into_iter().collect();
+        writeln!(text, "{}", line.trim_end())?;
+    }
+    Ok(())
+}
+
+/// Write the morgue file of a finished game into `dir`. Games with the same
+/// seed can end in the same second, so the name gets a number if need be.
+pub fn write_morgue(world: &World, dir: &Path) -> Result<PathBuf, SaveError> {
+    let ended_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let name = format!("morgue-{}-{}", world.game.seed, ended_at);
+    let path = (1..)
+        .map(|n| if n == 1 { dir.join(format!("{}.txt", name)) } else { dir.join(format!("{}-{}.txt", name, n)) })
+        .find(|path| !path.exists())
+        .expect("there are more names than files");
+    write_atomically(&path, morgue(world).as_bytes())?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use super::*;
+    use game::GameMode;
+
+    fn entry(score: u32, seed: u64) -> HighScore {
+        HighScore { score: score, won: false, deepest_level: 1, character_level: 1, xp: 0, kills: 0, turns: 0,
+                    seed: seed, ended_at: 0 }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("roguelike-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn scores(path: &Path) -> Vec<(u32, u64)> {
+        load_high_scores(path).unwrap().iter().map(|entry| (entry.score, entry.seed)).collect()
+    }
+
+    #[test]
+    fn high_scores_are_ranked_best_first() {
+        let dir = temp_dir("ranking");
+        let path = high_scores_path(&dir);
+        assert_eq!(load_high_scores(&path).unwrap(), vec![]);
+        assert_eq!(record_high_score(&path, entry(100, 1)).unwrap(), Some(0));
+        assert_eq!(record_high_score(&path, entry(300, 2)).unwrap(), Some(0));
+        assert_eq!(record_high_score(&path, entry(200, 3)).unwrap(), Some(1));
+        // ties go to the earlier game
+        assert_eq!(record_high_score(&path, entry(200, 4)).unwrap(), Some(2));
+        assert_eq!(scores(&path), vec![(300, 2), (200, 3), (200, 4), (100, 1)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_the_best_high_scores_are_kept() {
+        let dir = temp_dir("truncation");
+        let path = high_scores_path(&dir);
+        for seed in 0 .. MAX_HIGH_SCORES as u64 {
+            assert!(record_high_score(&path, entry(10 + seed as u32 * 10, seed)).unwrap().is_some());
+        }
+        assert_eq!(record_high_score(&path, entry(5, 100)).unwrap(), None);
+        // as good as the worst isn't good enough
+        assert_eq!(record_high_score(&path, entry(10, 101)).unwrap(), None);
+        assert_eq!(record_high_score(&path, entry(1000, 102)).unwrap(), Some(0));
+
+        let scores = scores(&path);
+        assert_eq!(scores.len(), MAX_HIGH_SCORES);
+        assert_eq!(scores[0], (1000, 102));
+        assert_eq!(scores[MAX_HIGH_SCORES - 1], (20, 1));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn morgue_files_are_never_overwritten() {
+        let dir = temp_dir("morgues");
+        let world = World::new(3, GameMode::Exploration);
+        let paths: Vec<_> = (0..3).map(|_| write_morgue(&world, &dir).unwrap()).collect();
+        assert!(paths[0] != paths[1] && paths[1] != paths[2] && paths[0] != paths[2]);
+        assert!(paths.iter().all(|path| path.exists()));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,232 @@
+//! Themes give the levels of the dungeon their character: how they are
+//! carved, their colours, what lives there and what lies around. Each level
+//! gets one when it's made, picked by depth, and keeps it.
+
+use rand::Rng;
+use rand::distributions::{Weighted, WeightedChoice, IndependentSample};
+
+use colors::Color;
+use map::{choose_generator, from_dungeon_level, Generator, Terrain, Transition};
+use object::Item;
+use rng::GameRng;
+
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable, RustcDecodable)]
+pub enum Theme {
+    /// Rooms and corridors, as the dungeon always was.
+    Dungeon,
+    /// Rooms of the dead, where skeletons and zombies walk.
+    Crypt,
+    /// Caves full of rubble, dug out by goblins, where blind crawlers lurk.
+    Mine,
+    /// Halls with rivers running through them and water everywhere.
+    FloodedHalls,
+    /// The boss's lair, on a depth of its own.
+    Lair,
+}
+
+/// How much of each kind of terrain a level gets.
+pub struct TerrainChances {
+    /// The chance of a river, in percent.
+    pub river: u32,
+    /// At most how many pools of water, patches of rubble and pools of lava.
+    pub water_pools: u32,
+    pub rubble_patches: u32,
+    pub lava_pools: u32,
+    /// The chance of a chasm, in percent.
+    pub chasm: u32,
+}
+
+impl Theme {
+    /// The theme of a new level: the lair on the boss's depth, and otherwise
+    /// one of those found that deep.
+    pub fn for_depth(depth: u32, boss_depth: u32, rng: &mut GameRng) -> Self {
+        if depth == boss_depth {
+            return Theme::Lair;
+        }
+        let theme_chances = &mut [
+            Weighted { weight: from_dungeon_level(&[
+                Transition { level: 1, value: 100 },
+                Transition { level: 4, value: 50 },
+                Transition { level: 7, value: 30 },
+                ], depth), item: Theme::Dungeon },
+            Weighted { weight: from_dungeon_level(&[
+                Transition { level: 2, value: 20 },
+                Transition { level: 5, value: 30 },
+                ], depth), item: Theme::Crypt },
+            Weighted { weight: from_dungeon_level(&[
+                Transition { level: 3, value: 20 },
+                Transition { level: 6, value: 30 },
+                ], depth), item: Theme::Mine },
+            Weighted { weight: from_dungeon_level(&[
+                Transition { level: 4, value: 20 },
+                Transition { level: 7, value: 30 },
+                ], depth), item: Theme::FloodedHalls },
+        ];
+        WeightedChoice::new(theme_chances).ind_sample(rng)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Theme::Dungeon => "dungeon",
+            Theme::Crypt => "crypt",
+            Theme::Mine => "mine",
+            Theme::FloodedHalls => "flooded halls",
+            Theme::Lair => "lair",
+        }
+    }
+
+    /// What the player is told on first setting foot on a level.
+    pub fn arrival_message(self) -> Option<&'static str> {
+        match self {
+            Theme::Dungeon => None,
+            Theme::Crypt => Some("The air is cold, and smells of old bones."),
+            Theme::Mine => Some("Picks and broken carts lie around: this was a mine once."),
+            Theme::FloodedHalls => Some("Water drips from the ceiling, and the halls ahead are flooded."),
+            Theme::Lair => Some("The floor is littered with gnawed bones. Something big lives here."),
+        }
+    }
+
+    /// Which generator makes a level of this theme.
+    pub fn generator(self, level: u32, rng: &mut GameRng) -> Generator {
+        match self {
+            Theme::Dungeon => choose_generator(level, rng),
+            Theme::Crypt => if rng.gen() { Generator::Rooms } else { Generator::Bsp },
+            Theme::Mine => Generator::Caves,
+            Theme::FloodedHalls => Generator::Bsp,
+            Theme::Lair => Generator::Lair,
+        }
+    }
+
+    pub fn terrain_chances(self, level: u32) -> TerrainChances {
+        let chasm = from_dungeon_level(&[
+            Transition { level: 3, value: 15 },
+            Transition { level: 6, value: 25 },
+            ], level);
+        let lava_pools = from_dungeon_level(&[
+            Transition { level: 4, value: 1 },
+            Transition { level: 7, value: 2 },
+            ], level);
+        match self {
+            Theme::Dungeon => TerrainChances {
+                river: from_dungeon_level(&[
+                    Transition { level: 2, value: 25 },
+                    Transition { level: 5, value: 40 },
+                    ], level),
+                water_pools: 0,
+                rubble_patches: 2,
+                lava_pools: lava_pools,
+                chasm: chasm,
+            },
+            Theme::Crypt => TerrainChances { river: 0, water_pools: 0, rubble_patches: 4, lava_pools: 0, chasm: 0 },
+            Theme::Mine => TerrainChances {
+                river: 10,
+                water_pools: 1,
+                rubble_patches: 10,
+                lava_pools: lava_pools,
+                chasm: chasm * 2,
+            },
+            Theme::FloodedHalls => TerrainChances {
+                river: 100,
+                water_pools: 6,
+                rubble_patches: 2,
+                lava_pools: 0,
+                chasm: 0,
+            },
+            Theme::Lair => TerrainChances { river: 0, water_pools: 0, rubble_patches: 2, lava_pools: 3, chasm: 0 },
+        }
+    }
+
+    /// The monsters of the theme, and how common each is.
+    pub fn monster_chances(self, level: u32) -> Vec<Weighted<&'static str>> {
+        let troll_chance = from_dungeon_level(&[
+            Transition { level: 3, value: 15 },
+            Transition { level: 5, value: 30 },
+            Transition { level: 7, value: 60 },
+            ], level);
+        let archer_chance = from_dungeon_level(&[
+            Transition { level: 2, value: 10 },
+            Transition { level: 5, value: 20 },
+            ], level);
+        match self {
+            Theme::Dungeon => vec![
+                Weighted { weight: 80, item: "orc" },
+                Weighted { weight: troll_chance, item: "troll" },
+                Weighted { weight: archer_chance, item: "goblin archer" },
+                Weighted { weight: from_dungeon_level(&[
+                    Transition { level: 3, value: 10 },
+                    Transition { level: 6, value: 20 },
+                    ], level), item: "kobold shaman" },
+            ],
+            Theme::Crypt => vec![
+                Weighted { weight: 60, item: "skeleton" },
+                Weighted { weight: from_dungeon_level(&[
+                    Transition { level: 1, value: 10 },
+                    Transition { level: 4, value: 30 },
+                    Transition { level: 7, value: 50 },
+                    ], level), item: "zombie" },
+                Weighted { weight: 20, item: "orc" },
+            ],
+            Theme::Mine => vec![
+                Weighted { weight: 60, item: "goblin" },
+                Weighted { weight: archer_chance * 2, item: "goblin archer" },
+                Weighted { weight: 20, item: "orc" },
+                Weighted { weight: from_dungeon_level(&[
+                    Transition { level: 3, value: 20 },
+                    ], level), item: "cave crawler" },
+                Weighted { weight: troll_chance, item: "troll" },
+            ],
+            Theme::FloodedHalls => vec![
+                Weighted { weight: 50, item: "lizardman" },
+                Weighted { weight: 40, item: "orc" },
+                Weighted { weight: troll_chance, item: "troll" },
+            ],
+            Theme::Lair => vec![
+                Weighted { weight: 60, item: "orc" },
+                Weighted { weight: 40, item: "troll" },
+            ],
+        }
+    }
+
+    /// How much more or less common an item is than usual, in percent.
+    pub fn loot_bias(self, item: Item) -> u32 {
+        match (self, item) {
+            (Theme::Crypt, Item::Lightning) | (Theme::Crypt, Item::Confuse) | (Theme::Crypt, Item::Fireball) => 200,
+            (Theme::Crypt, Item::Sword) | (Theme::Crypt, Item::Shield) => 50,
+            (Theme::Mine, Item::Sword) | (Theme::Mine, Item::Shield) => 300,
+            (Theme::Mine, Item::Lightning) | (Theme::Mine, Item::Confuse) | (Theme::Mine, Item::Fireball) => 50,
+            (Theme::FloodedHalls, Item::Heal) => 150,
+            (Theme::Lair, Item::Heal) => 200,
+            _ => 100,
+        }
+    }
+
+    /// The background of a terrain out of sight.
+    pub fn dark_color(self, terrain: Terrain) -> Color {
+        match (self, terrain) {
+            (Theme::Crypt, Terrain::Wall) => Color { r: 20, g: 20, b: 60 },
+            (Theme::Crypt, Terrain::Floor) => Color { r: 45, g: 45, b: 100 },
+            (Theme::Mine, Terrain::Wall) => Color { r: 20, g: 10, b: 70 },
+            (Theme::Mine, Terrain::Floor) => Color { r: 55, g: 40, b: 120 },
+            (Theme::FloodedHalls, Terrain::Wall) => Color { r: 0, g: 20, b: 90 },
+            (Theme::FloodedHalls, Terrain::Floor) => Color { r: 40, g: 60, b: 140 },
+            (Theme::Lair, Terrain::Wall) => Color { r: 40, g: 0, b: 70 },
+            (Theme::Lair, Terrain::Floor) => Color { r: 70, g: 30, b: 110 },
+            (_, terrain) => terrain.dark_color(),
+        }
+    }
+
+    /// The background of a terrain in view.
+    pub fn light_color(self, terrain: Terrain) -> Color {
+        match (self, terrain) {
+            (Theme::Crypt, Terrain::Wall) => Color { r: 100, g: 100, b: 110 },
+            (Theme::Crypt, Terrain::Floor) => Color { r: 160, g: 150, b: 130 },
+            (Theme::Mine, Terrain::Wall) => Color { r: 120, g: 80, b: 40 },
+            (Theme::Mine, Terrain::Floor) => Color { r: 180, g: 140, b: 90 },
+            (Theme::FloodedHalls, Terrain::Wall) => Color { r: 60, g: 110, b: 110 },
+            (Theme::FloodedHalls, Terrain::Floor) => Color { r: 130, g: 170, b: 160 },
+            (Theme::Lair, Terrain::Wall) => Color { r: 110, g: 40, b: 30 },
+            (Theme::Lair, Terrain::Floor) => Color { r: 170, g: 100, b: 60 },
+            (_, terrain) => terrain.light_color(),
+        }
+    }
+}
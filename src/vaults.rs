@@ -0,0 +1,448 @@
+//! Hand-made rooms ("vaults") stamped into levels. Vaults are text files,
+//! one vault per file, so they can be written without touching the code:
+//!
+//! ```text
+//! name: Orc barracks
+//! depth: 3-8
+//! rarity: 4
+//! asleep: no
+//! ---
+//! #########
+//! #o.o.o.o#
+//! #...!...#
+//! #########
+//! ```
+//!
+//! The header says on which depths the vault can turn up and how rare it is:
+//! a vault of rarity 4 is on about one level in 4 of its depths, and whether
+//! its monsters start asleep (they are awake and on guard if not). Lines
+//! starting with `;` are comments. Below the `---` is the vault itself:
+//!
+//! * `#` is a wall, `.` is floor, `+` is a closed door, and a space leaves
+//!   the level as it is
+//! * `o` is an orc and `T` a troll
+//! * `!` is a healing potion, `L`, `C` and `F` are scrolls of lightning
+//!   bolt, confusion and fireball, `/` is a sword and `[` a shield
+//!
+//! A vault goes into solid rock, and a tunnel joins it to the rest of the
+//! level. The tunnel leads to one of the vault's doors that open to the
+//! outside, or if it has none, through its outer wall, and never through any
+//! other part of a vault.
+
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+
+use rand::Rng;
+
+use map::{make_item, make_monster, map_size, Door, Map, Tile};
+use object::{Ai, Item, Object};
+use rng::GameRng;
+
+/// How many places are tried for a vault before giving up on it.
+const VAULT_PLACEMENT_TRIES: usize = 50;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vault {
+    pub name: String,
+    pub min_depth: u32,
+    pub max_depth: u32,
+    /// The vault is on about one level in `rarity` of its depths.
+    pub rarity: u32,
+    /// Whether the monsters in the vault start asleep.
+    pub asleep: bool,
+    /// The glyphs of the vault, row by row.
+    pub rows: Vec<Vec<char>>,
+}
+
+impl Vault {
+    /// Parse the text of a vault file.
+    pub fn parse(name: &str, text: &str) -> Result<Self, String> {
+        let mut vault = Vault {
+            name: name.into(),
+            min_depth: 1,
+            max_depth: u32::max_value(),
+            rarity: 1,
+            asleep: false,
+            rows: vec![],
+        };
+        let mut lines = text.lines().enumerate();
+        for (number, line) in &mut lines {
+            let error = |message: &str| Err(format!("line {}: {}", number + 1, message));
+            let line = line.trim();
+            if line == "---" {
+                break;
+            }
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let mut parts = line.splitn(2, ':');
+            let key = parts.next().unwrap_or("").trim();
+            let value = match parts.next() {
+                Some(value) => value.trim(),
+                None => return error("expected 'key: value' or '---'"),
+            };
+            match key {
+                "name" => vault.name = value.into(),
+                "depth" => {
+                    let mut depths = value.splitn(2, '-').map(|depth| depth.trim().parse());
+                    match (depths.next(), depths.next()) {
+                        (Some(Ok(depth)), None) => {
+                            vault.min_depth = depth;
+                            vault.max_depth = depth;
+                        }
+                        (Some(Ok(min_depth)), Some(Ok(max_depth))) if min_depth <= max_depth => {
+                            vault.min_depth = min_depth;
+                            vault.max_depth = max_depth;
+                        }
+                        _ => return error("the depth must look like 3 or 3-8"),
+                    }
+                }
+                "rarity" => match value.parse() {
+                    Ok(rarity) if rarity > 0 => vault.rarity = rarity,
+                    _ => return error("the rarity must be a number from 1 up"),
+                },
+                "asleep" => match value {
+                    "yes" => vault.asleep = true,
+                    "no" => vault.asleep = false,
+                    _ => return error("asleep must be yes or no"),
+                },
+                _ => return error(&format!("unknown key '{}'", key)),
+            }
+        }
+
+        for (number, line) in lines {
+            let row: Vec<char> = line.trim_end().chars().collect();
+            if let Some(&glyph) = row.iter().find(|&&glyph| !is_glyph(glyph)) {
+                return Err(format!("line {}: unknown glyph '{}'", number + 1, glyph));
+            }
+            vault.rows.push(row);
+        }
+        while vault.rows.last().map_or(false, |row| row.is_empty()) {
+            vault.rows.pop();
+        }
+        if vault.rows.is_empty() {
+            return Err("the vault is empty".into());
+        }
+        Ok(vault)
+    }
+
+    pub fn width(&self) -> i32 {
+        self.rows.iter().map(|row| row.len()).max().unwrap_or(0) as i32
+    }
+
+    pub fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+
+    /// The glyph at a place in the vault; a space past the end of a row, or
+    /// anywhere outside the vault.
+    fn glyph(&self, x: i32, y: i32) -> char {
+        if y < 0 || y >= self.height() || x < 0 {
+            return ' ';
+        }
+        self.rows[y as usize].get(x as usize).cloned().unwrap_or(' ')
+    }
+
+    /// Where the vault can be entered from outside: the doors and walls on
+    /// its edge with floor behind them, each with the place in front of it.
+    /// Only the doors if there are any.
+    fn entrances(&self) -> Vec<((i32, i32), (i32, i32))> {
+        let mut doors = vec![];
+        let mut walls = vec![];
+        for y in 0 .. self.height() {
+            for x in 0 .. self.width() {
+                let glyph = self.glyph(x, y);
+                for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+                    let behind = self.glyph(x - dx, y - dy);
+                    if self.glyph(x + dx, y + dy) != ' ' || behind == ' ' || behind == '#' {
+                        continue;
+                    }
+                    match glyph {
+                        '+' => doors.push(((x, y), (x + dx, y + dy))),
+                        '#' => walls.push(((x, y), (x + dx, y + dy))),
+                        _ => {}
+                    }
+                }
+            }
+        }
+        if doors.is_empty() { walls } else { doors }
+    }
+}
+
+fn is_glyph(glyph: char) -> bool {
+    match glyph {
+        '#' | '.' | '+' | ' ' | 'o' | 'T' | '!' | 'L' | 'C' | 'F' | '/' | '[' => true,
+        _ => false,
+    }
+}
+
+/// Load all the vaults (`*.txt` files) in a directory. There are no vaults
+/// if the directory doesn't exist.
+pub fn load_vaults(dir: &Path) -> Result<Vec<Vault>, String> {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(vec![]),
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().map_or(false, |ext| ext == "txt"))
+        .collect();
+    // the order vaults are tried in decides the level a seed makes
+    paths.sort();
+
+    let mut vaults = vec![];
+    for path in paths {
+        let mut text = String::new();
+        File::open(&path)
+            .and_then(|mut file| file.read_to_string(&mut text))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+        let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().into_owned());
+        vaults.push(Vault::parse(&name, &text).map_err(|e| format!("{} {}", path.display(), e))?);
+    }
+    Ok(vaults)
+}
+
+/// Stamp the vaults meant for this depth into a freshly carved level, where
+/// they fit.
+pub fn place_vaults(vaults: &[Vault], map: &mut Map, objects: &mut Vec<Object>, level: u32,
+        rng: &mut GameRng) {
+    let (width, height) = map_size(map);
+    // no tunnel goes through a vault, but into it
+    let mut in_vault = vec![vec![false; height as usize]; width as usize];
+    for vault in vaults {
+        // leave room for a wall around the vault, and the map's border
+        let too_big = vault.width() > width - 4 || vault.height() > height - 4;
+        if too_big || level < vault.min_depth || level > vault.max_depth
+                || rng.gen_range(0, vault.rarity) != 0 {
+            continue;
+        }
+        for _ in 0 .. VAULT_PLACEMENT_TRIES {
+            let x = rng.gen_range(2, width - vault.width() - 1);
+            let y = rng.gen_range(2, height - vault.height() - 1);
+            if fits(vault, x, y, map) {
+                stamp(vault, x, y, map, objects);
+                for vault_y in 0 .. vault.height() {
+                    for vault_x in 0 .. vault.width() {
+                        if vault.glyph(vault_x, vault_y) != ' ' {
+                            in_vault[(x + vault_x) as usize][(y + vault_y) as usize] = true;
+                        }
+                    }
+                }
+                dig_entrance(vault, x, y, map, &in_vault);
+                break;
+            }
+        }
+    }
+}
+
+/// Whether a vault would only cover solid rock, with a wall all around it.
+fn fits(vault: &Vault, x: i32, y: i32, map: &Map) -> bool {
+    (x - 1 .. x + vault.width() + 1).all(|map_x| {
+        (y - 1 .. y + vault.height() + 1).all(|map_y| map[map_x as usize][map_y as usize].blocked)
+    })
+}
+
+fn stamp(vault: &Vault, x: i32, y: i32, map: &mut Map, objects: &mut Vec<Object>) {
+    for vault_y in 0 .. vault.height() {
+        for vault_x in 0 .. vault.width() {
+            let (map_x, map_y) = (x + vault_x, y + vault_y);
+            let glyph = vault.glyph(vault_x, vault_y);
+            match glyph {
+                ' ' => continue,
+                '#' => {
+                    map[map_x as usize][map_y as usize] = Tile::wall();
+                    continue;
+                }
+                '+' => {
+                    map[map_x as usize][map_y as usize] = Tile::door(Door::Closed);
+                    continue;
+                }
+                _ => {}
+            }
+            map[map_x as usize][map_y as usize] = Tile::empty();
+            let mut object = match glyph {
+                'o' => Some(make_monster("orc", map_x, map_y)),
+                'T' => Some(make_monster("troll", map_x, map_y)),
+                '!' => Some(make_item(Item::Heal, map_x, map_y)),
+                'L' => Some(make_item(Item::Lightning, map_x, map_y)),
+                'C' => Some(make_item(Item::Confuse, map_x, map_y)),
+                'F' => Some(make_item(Item::Fireball, map_x, map_y)),
+                '/' => Some(make_item(Item::Sword, map_x, map_y)),
+                '[' => Some(make_item(Item::Shield, map_x, map_y)),
+                _ => None,
+            };
+            if let Some(ref mut monster) = object {
+                if vault.asleep && monster.ai.is_some() {
+                    monster.ai = Some(Ai::Asleep);
+                }
+            }
+            objects.extend(object);
+        }
+    }
+}
+
+/// Tunnel from in front of the entrance of a vault stamped at `x`, `y` to
+/// the nearest floor of the level, going around the vaults, and open the
+/// entrance if it's a wall.
+fn dig_entrance(vault: &Vault, x: i32, y: i32, map: &mut Map, in_vault: &[Vec<bool>]) {
+    let (width, height) = map_size(map);
+    // a search from the front of all the entrances at once, for the floor
+    let mut came_from = vec![vec![None; height as usize]; width as usize];
+    let mut tiles = vec![];
+    for &((entrance_x, entrance_y), (front_x, front_y)) in &vault.entrances() {
+        let (entrance, front) = ((x + entrance_x, y + entrance_y), (x + front_x, y + front_y));
+        if front.0 < 1 || front.1 < 1 || front.0 >= width - 1 || front.1 >= height - 1 {
+            continue;
+        }
+        if came_from[front.0 as usize][front.1 as usize].is_none() {
+            came_from[front.0 as usize][front.1 as usize] = Some(entrance);
+            tiles.push(front);
+        }
+    }
+    let mut next = 0;
+    while next < tiles.len() {
+        let (tile_x, tile_y) = tiles[next];
+        next += 1;
+        if !map[tile_x as usize][tile_y as usize].blocked {
+            // found the floor: dig back to the vault
+            let mut pos = (tile_x, tile_y);
+            while !in_vault[pos.0 as usize][pos.1 as usize] {
+                if map[pos.0 as usize][pos.1 as usize].blocked {
+                    map[pos.0 as usize][pos.1 as usize] = Tile::empty();
+                }
+                pos = came_from[pos.0 as usize][pos.1 as usize].unwrap();
+            }
+            if map[pos.0 as usize][pos.1 as usize].door.is_none() {
+                map[pos.0 as usize][pos.1 as usize] = Tile::empty();
+            }
+            return;
+        }
+        for &(dx, dy) in &[(0, -1), (1, 0), (0, 1), (-1, 0)] {
+            let (next_x, next_y) = (tile_x + dx, tile_y + dy);
+            if next_x < 1 || next_y < 1 || next_x >= width - 1 || next_y >= height - 1 {
+                continue;
+            }
+            if came_from[next_x as usize][next_y as usize].is_none() && !in_vault[next_x as usize][next_y as usize] {
+                came_from[next_x as usize][next_y as usize] = Some((tile_x, tile_y));
+                tiles.push((next_x, next_y));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use map::flood_fill;
+
+    const DEN: &'static str = "asleep: yes
+---
+  ####
+ ##..##
+ #.T..#
+ ##..##
+  ####";
+
+    const GATEHOUSE: &'static str = "---
+#####
+#.o.#
+#...+
+#####";
+
+    /// Solid rock with a room at the left end.
+    fn rock_and_room() -> Map {
+        let mut map = vec![vec![Tile::wall(); 20]; 50];
+        for column in &mut map[2 .. 8] {
+            for tile in &mut column[5 .. 15] {
+                *tile = Tile::empty();
+            }
+        }
+        map
+    }
+
+    fn place(vault: &Vault, x: i32, y: i32, map: &mut Map, objects: &mut Vec<Object>,
+            in_vault: &mut Vec<Vec<bool>>) {
+        assert!(fits(vault, x, y, map));
+        stamp(vault, x, y, map, objects);
+        for vault_y in 0 .. vault.height() {
+            for vault_x in 0 .. vault.width() {
+                if vault.glyph(vault_x, vault_y) != ' ' {
+                    in_vault[(x + vault_x) as usize][(y + vault_y) as usize] = true;
+                }
+            }
+        }
+        dig_entrance(vault, x, y, map, in_vault);
+    }
+
+    /// The vault's walls that aren't walls any more.
+    fn opened(vault: &Vault, x: i32, y: i32, map: &Map) -> Vec<(i32, i32)> {
+        let mut opened = vec![];
+        for vault_y in 0 .. vault.height() {
+            for vault_x in 0 .. vault.width() {
+                let tile = &map[(x + vault_x) as usize][(y + vault_y) as usize];
+                if vault.glyph(vault_x, vault_y) == '#' && !tile.blocked {
+                    opened.push((vault_x, vault_y));
+                }
+            }
+        }
+        opened
+    }
+
+    #[test]
+    fn vaults_are_entered_through_their_outer_wall() {
+        let den = Vault::parse("den", DEN).unwrap();
+        let (mut map, mut objects) = (rock_and_room(), vec![]);
+        let mut in_vault = vec![vec![false; 20]; 50];
+        place(&den, 30, 7, &mut map, &mut objects, &mut in_vault);
+
+        let opened = opened(&den, 30, 7, &map);
+        assert_eq!(opened.len(), 1);
+        assert!(den.entrances().iter().any(|&(entrance, _)| entrance == opened[0]));
+        let reached = flood_fill(&map, (2, 5));
+        assert!(reached.contains(&(33, 9)));
+    }
+
+    #[test]
+    fn vaults_with_doors_are_entered_through_them() {
+        let gatehouse = Vault::parse("gatehouse", GATEHOUSE).unwrap();
+        assert_eq!(gatehouse.entrances(), vec![((4, 2), (5, 2))]);
+        let (mut map, mut objects) = (rock_and_room(), vec![]);
+        let mut in_vault = vec![vec![false; 20]; 50];
+        place(&gatehouse, 30, 7, &mut map, &mut objects, &mut in_vault);
+
+        assert_eq!(opened(&gatehouse, 30, 7, &map), vec![]);
+        assert_eq!(map[34][9].door, Some(Door::Closed));
+        // the way leads around the vault to its door
+        assert!(!map[35][9].blocked);
+        assert!(flood_fill(&map, (2, 5)).contains(&(35, 9)));
+    }
+
+    #[test]
+    fn tunnels_go_around_other_vaults() {
+        let den = Vault::parse("den", DEN).unwrap();
+        let gatehouse = Vault::parse("gatehouse", GATEHOUSE).unwrap();
+        let (mut map, mut objects) = (rock_and_room(), vec![]);
+        let mut in_vault = vec![vec![false; 20]; 50];
+        place(&den, 14, 7, &mut map, &mut objects, &mut in_vault);
+        place(&gatehouse, 30, 8, &mut map, &mut objects, &mut in_vault);
+
+        assert_eq!(opened(&den, 14, 7, &map).len(), 1);
+        assert_eq!(opened(&gatehouse, 30, 8, &map), vec![]);
+        let reached = flood_fill(&map, (2, 5));
+        assert!(reached.contains(&(17, 9)));
+        assert!(reached.contains(&(32, 9)));
+    }
+
+    #[test]
+    fn monsters_sleep_in_vaults_that_say_so() {
+        let mut map = rock_and_room();
+        let mut objects = vec![];
+        stamp(&Vault::parse("den", DEN).unwrap(), 30, 7, &mut map, &mut objects);
+        stamp(&Vault::parse("gatehouse", GATEHOUSE).unwrap(), 30, 1, &mut map, &mut objects);
+        assert_eq!(objects.len(), 2);
+        assert!(objects[0].name == "troll" && matches!(objects[0].ai, Some(Ai::Asleep)));
+        assert!(objects[1].name == "orc" && matches!(objects[1].ai, Some(Ai::Basic)));
+        assert!(Vault::parse("den", "asleep: maybe\n---\n#").unwrap_err().contains("line 1"));
+    }
+}